@@ -0,0 +1,43 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use invariant_types::decimals::{Decimal, FixedPoint};
+use jupiter_core::amm::{Amm, QuoteParams};
+use jupiter_invariant::{swap::SwapMode, test_utils::PoolBuilder, JupiterInvariant};
+
+// 0.01% fee, positions every 10 ticks around the price as on a stable pair
+fn pool() -> (PoolBuilder, JupiterInvariant) {
+    let builder = (1..=40).fold(
+        PoolBuilder::new(1, FixedPoint::new(100000000)),
+        |builder, offset| builder.position(-10 * offset, 10 * offset, 1_000_000_000000),
+    );
+    let jupiter_invariant = builder.build().unwrap();
+    (builder, jupiter_invariant)
+}
+
+fn bench_quote(c: &mut Criterion) {
+    let (builder, jupiter_invariant) = pool();
+
+    for swap_mode in [SwapMode::ExactIn, SwapMode::ExactOut] {
+        let jupiter_invariant = JupiterInvariant {
+            swap_mode,
+            ..jupiter_invariant.clone()
+        };
+        let mut group = c.benchmark_group(format!("quote_{:?}", swap_mode));
+        // from no crossed ticks up to the whole window of ticks
+        for in_amount in [1_000u64, 100_000, 10_000_000, 100_000_000] {
+            let quote_params = QuoteParams {
+                in_amount,
+                input_mint: builder.token_x,
+                output_mint: builder.token_y,
+            };
+            group.bench_with_input(
+                BenchmarkId::from_parameter(in_amount),
+                &quote_params,
+                |b, quote_params| b.iter(|| jupiter_invariant.quote(black_box(quote_params))),
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_quote);
+criterion_main!(benches);
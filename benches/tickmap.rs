@@ -0,0 +1,75 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use invariant_types::structs::{Tickmap, TICK_CROSSES_PER_IX, TICK_LIMIT};
+use jupiter_invariant::tickmap::{
+    find_closest_positions, find_closest_positions_bitwise, PriceDirection,
+};
+
+fn set_position(tickmap: &mut Tickmap, position: i32) {
+    tickmap.bitmap[position as usize / 8] |= 1 << (position % 8);
+}
+
+// (name, bitmap) pairs resembling mainnet pools, positions are relative to the tick spacing
+fn bitmaps() -> Vec<(&'static str, Tickmap)> {
+    // stable pair, liquidity concentrated around the current price
+    let mut concentrated = Tickmap::default();
+    for offset in -50..50 {
+        set_position(&mut concentrated, TICK_LIMIT + offset * 2);
+    }
+    // volatile pair with wide spacing, a few positions far away from each other
+    let mut sparse = Tickmap::default();
+    for offset in [-30000, -12000, -4000, -700, 900, 5000, 16000, 35000] {
+        set_position(&mut sparse, TICK_LIMIT + offset);
+    }
+    // single position close to the price
+    let mut single = Tickmap::default();
+    set_position(&mut single, TICK_LIMIT - 10);
+    set_position(&mut single, TICK_LIMIT + 10);
+
+    vec![
+        ("concentrated", concentrated),
+        ("sparse", sparse),
+        ("single", single),
+    ]
+}
+
+fn bench_find_closest_positions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_closest_positions");
+
+    for (name, tickmap) in bitmaps() {
+        for direction in [PriceDirection::UP, PriceDirection::DOWN] {
+            let parameter = format!("{}/{:?}", name, direction);
+            group.bench_with_input(
+                BenchmarkId::new("word", &parameter),
+                &tickmap,
+                |b, tickmap| {
+                    b.iter(|| {
+                        find_closest_positions(
+                            black_box(&tickmap.bitmap),
+                            black_box(TICK_LIMIT),
+                            TICK_CROSSES_PER_IX,
+                            direction,
+                        )
+                    })
+                },
+            );
+            group.bench_with_input(
+                BenchmarkId::new("bitwise", &parameter),
+                &tickmap,
+                |b, tickmap| {
+                    b.iter(|| {
+                        find_closest_positions_bitwise(
+                            black_box(&tickmap.bitmap),
+                            black_box(TICK_LIMIT),
+                            TICK_CROSSES_PER_IX,
+                            direction,
+                        )
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_closest_positions);
+criterion_main!(benches);
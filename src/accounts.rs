@@ -1,8 +1,15 @@
+use std::collections::HashMap;
+use std::sync::{OnceLock, PoisonError, RwLock};
+
 use anchor_lang::prelude::*;
-use anyhow::Error;
-use invariant_types::{SEED, STATE_SEED};
+use invariant_types::{structs::TICK_CROSSES_PER_IX, ANCHOR_DISCRIMINATOR_SIZE, SEED, STATE_SEED};
+use solana_sdk::instruction::Instruction;
 
-use crate::{JupiterInvariant, swap::InvariantSwapResult};
+use crate::{
+    error::InvariantError,
+    swap::{InvariantSimulationParams, InvariantSwapResult},
+    JupiterInvariant,
+};
 
 #[derive(Clone)]
 pub struct InvariantSwapParams<'a> {
@@ -15,6 +22,50 @@ pub struct InvariantSwapParams<'a> {
     pub referral_fee: Option<Pubkey>,
 }
 
+// arguments of the `swap` instruction of the Invariant program, in the order of the handler
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, AnchorSerialize, AnchorDeserialize)]
+pub struct InvariantSwapArgs {
+    pub x_to_y: bool,
+    // exact input amount, or exact output amount when `by_amount_in` is false
+    pub amount: u64,
+    pub by_amount_in: bool,
+    pub sqrt_price_limit: u128,
+}
+
+impl InvariantSwapArgs {
+    // sha256("global:swap")[..8]
+    pub const DISCRIMINATOR: [u8; ANCHOR_DISCRIMINATOR_SIZE] =
+        [248, 198, 158, 145, 225, 117, 135, 200];
+
+    pub fn discriminator() -> [u8; ANCHOR_DISCRIMINATOR_SIZE] {
+        Self::DISCRIMINATOR
+    }
+
+    pub fn data(&self) -> Vec<u8> {
+        let mut data = Self::discriminator().to_vec();
+        self.serialize(&mut data)
+            .expect("serialization into a vector cannot fail");
+        data
+    }
+}
+
+impl From<&InvariantSimulationParams> for InvariantSwapArgs {
+    fn from(params: &InvariantSimulationParams) -> Self {
+        Self {
+            x_to_y: params.x_to_y,
+            amount: params.in_amount,
+            by_amount_in: params.by_amount_in,
+            sqrt_price_limit: params.sqrt_price_limit.v,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ProgramAddresses {
+    state: Pubkey,
+    program_authority: Pubkey,
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct InvariantSwapAccounts {
     state: Pubkey,
@@ -35,7 +86,7 @@ impl InvariantSwapAccounts {
     pub fn from_pubkeys(
         jupiter_invariant: &JupiterInvariant,
         invariant_swap_params: &InvariantSwapParams,
-    ) -> anyhow::Result<(Self, bool), Error> {
+    ) -> Result<(Self, bool), InvariantError> {
         let InvariantSwapParams {
             invariant_swap_result,
             owner,
@@ -54,11 +105,22 @@ impl InvariantSwapAccounts {
         ) {
             (true, true, _, _) => (true, *source_account, *destination_account),
             (_, _, true, true) => (false, *destination_account, *source_account),
-            _ => return Err(anyhow::Error::msg("Invalid source or destination mint")),
+            _ => return Err(InvariantError::InvalidMintPair),
         };
-        // possibility update: add one tick in the opposite direction to swap direction
-        let ticks_accounts =
+        let mut ticks_accounts =
             jupiter_invariant.tick_indexes_to_addresses(&invariant_swap_result.crossed_ticks);
+        // the quote approves the crossed ticks only, so the opposite tick takes a spare account
+        let max_ticks_accounts = TICK_CROSSES_PER_IX - usize::from(referral_fee.is_some());
+        if jupiter_invariant.ticks_window.opposite_tick && ticks_accounts.len() < max_ticks_accounts
+        {
+            if let Some(address) = jupiter_invariant.opposite_tick_address(x_to_y)? {
+                if jupiter_invariant.ticks.contains_key(&address)
+                    && !ticks_accounts.contains(&address)
+                {
+                    ticks_accounts.push(address);
+                }
+            }
+        }
 
         let invariant_swap_accounts = Self {
             state: Self::get_state_address(jupiter_invariant.program_id),
@@ -104,11 +166,226 @@ impl InvariantSwapAccounts {
         account_metas
     }
 
+    pub fn to_instruction(&self, program_id: Pubkey, args: &InvariantSwapArgs) -> Instruction {
+        Instruction {
+            program_id,
+            accounts: self.to_account_metas(),
+            data: args.data(),
+        }
+    }
+
     fn get_program_authority(program_id: Pubkey) -> Pubkey {
-        Pubkey::find_program_address(&[SEED.as_bytes()], &program_id).0
+        Self::get_program_addresses(program_id).program_authority
     }
 
     fn get_state_address(program_id: Pubkey) -> Pubkey {
-        Pubkey::find_program_address(&[STATE_SEED.as_bytes()], &program_id).0
+        Self::get_program_addresses(program_id).state
+    }
+
+    // program-level addresses are derived once per program
+    fn get_program_addresses(program_id: Pubkey) -> ProgramAddresses {
+        static PROGRAM_ADDRESSES: OnceLock<RwLock<HashMap<Pubkey, ProgramAddresses>>> =
+            OnceLock::new();
+        let cache = PROGRAM_ADDRESSES.get_or_init(Default::default);
+
+        let cached = cache
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&program_id)
+            .copied();
+        cached.unwrap_or_else(|| {
+            let program_addresses = ProgramAddresses {
+                state: Pubkey::find_program_address(&[STATE_SEED.as_bytes()], &program_id).0,
+                program_authority: Pubkey::find_program_address(&[SEED.as_bytes()], &program_id).0,
+            };
+            let _ = cache
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(program_id, program_addresses);
+            program_addresses
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anchor_lang::{prelude::Pubkey, solana_program::hash::hash, AnchorDeserialize};
+    use invariant_types::{
+        decimals::{Decimal, FixedPoint},
+        structs::TICK_CROSSES_PER_IX,
+        SEED, STATE_SEED,
+    };
+    use jupiter_core::amm::{Amm, QuoteParams, SwapParams};
+
+    use crate::{
+        accounts::{InvariantSwapAccounts, InvariantSwapArgs, InvariantSwapParams},
+        swap::{InvariantSwapResult, SwapMode},
+        test_utils::PoolBuilder,
+    };
+
+    #[test]
+    fn test_opposite_tick() {
+        let builder = PoolBuilder::new(10, FixedPoint::new(0))
+            .current_tick_index(10)
+            .position(-100, 100, 1_000_000_000000)
+            .position(-20, 20, 1_000_000_000000);
+        let mut jupiter_invariant = builder.build().unwrap();
+        let quote_params = QuoteParams {
+            in_amount: 10,
+            input_mint: builder.token_x,
+            output_mint: builder.token_y,
+        };
+        let params = jupiter_invariant
+            .quote_to_invariant_params(&quote_params)
+            .unwrap();
+        let invariant_swap_result = jupiter_invariant.simulate_invariant_swap(&params).unwrap();
+        let invariant_swap_params = InvariantSwapParams {
+            invariant_swap_result: &invariant_swap_result,
+            owner: Pubkey::new_unique(),
+            source_mint: builder.token_x,
+            destination_mint: builder.token_y,
+            source_account: Pubkey::new_unique(),
+            destination_account: Pubkey::new_unique(),
+            referral_fee: None,
+        };
+        assert!(invariant_swap_result.crossed_ticks.is_empty());
+
+        let (accounts, _) =
+            InvariantSwapAccounts::from_pubkeys(&jupiter_invariant, &invariant_swap_params)
+                .unwrap();
+        assert!(accounts.ticks_accounts.is_empty());
+
+        jupiter_invariant.ticks_window.opposite_tick = true;
+        let (accounts, _) =
+            InvariantSwapAccounts::from_pubkeys(&jupiter_invariant, &invariant_swap_params)
+                .unwrap();
+        assert_eq!(
+            accounts.ticks_accounts,
+            vec![jupiter_invariant.tick_index_to_address(20)]
+        );
+    }
+
+    #[test]
+    fn test_opposite_tick_at_crossing_limit() {
+        let builder = (1..=TICK_CROSSES_PER_IX as i32).fold(
+            PoolBuilder::new(10, FixedPoint::new(0)),
+            |builder, index| builder.position(-10 * index, 10, 1_000_000_000000),
+        );
+        let mut jupiter_invariant = builder.build().unwrap();
+        jupiter_invariant.ticks_window.opposite_tick = true;
+        let opposite_tick = jupiter_invariant.tick_index_to_address(10);
+        let ticks_accounts = |crossed_ticks: usize, referral_fee: Option<Pubkey>| {
+            let invariant_swap_result = InvariantSwapResult {
+                crossed_ticks: (1..=crossed_ticks as i32)
+                    .map(|index| -10 * index)
+                    .collect(),
+                ..Default::default()
+            };
+            let invariant_swap_params = InvariantSwapParams {
+                invariant_swap_result: &invariant_swap_result,
+                owner: Pubkey::new_unique(),
+                source_mint: builder.token_x,
+                destination_mint: builder.token_y,
+                source_account: Pubkey::new_unique(),
+                destination_account: Pubkey::new_unique(),
+                referral_fee,
+            };
+            let (accounts, _) =
+                InvariantSwapAccounts::from_pubkeys(&jupiter_invariant, &invariant_swap_params)
+                    .unwrap();
+            accounts.ticks_accounts
+        };
+
+        // a spare account is left
+        let accounts = ticks_accounts(TICK_CROSSES_PER_IX - 1, None);
+        assert_eq!(accounts.len(), TICK_CROSSES_PER_IX);
+        assert_eq!(accounts.last(), Some(&opposite_tick));
+        // the crossed ticks take all accounts
+        let accounts = ticks_accounts(TICK_CROSSES_PER_IX, None);
+        assert_eq!(accounts.len(), TICK_CROSSES_PER_IX);
+        assert!(!accounts.contains(&opposite_tick));
+        // the referral fee account takes the spare one
+        let accounts = ticks_accounts(TICK_CROSSES_PER_IX - 1, Some(Pubkey::new_unique()));
+        assert_eq!(accounts.len(), TICK_CROSSES_PER_IX - 1);
+        assert!(!accounts.contains(&opposite_tick));
+    }
+
+    #[test]
+    fn test_program_addresses() {
+        let program_id = invariant_types::ID;
+
+        for _ in 0..2 {
+            assert_eq!(
+                InvariantSwapAccounts::get_state_address(program_id),
+                Pubkey::find_program_address(&[STATE_SEED.as_bytes()], &program_id).0
+            );
+            assert_eq!(
+                InvariantSwapAccounts::get_program_authority(program_id),
+                Pubkey::find_program_address(&[SEED.as_bytes()], &program_id).0
+            );
+        }
+    }
+
+    #[test]
+    fn test_swap_instruction() {
+        let builder =
+            PoolBuilder::new(10, FixedPoint::new(0)).position(-100, 100, 1_000_000_000000);
+        let mut jupiter_invariant = builder.build().unwrap();
+        let swap_params = SwapParams {
+            source_mint: builder.token_y,
+            destination_mint: builder.token_x,
+            user_source_token_account: Pubkey::new_unique(),
+            user_destination_token_account: Pubkey::new_unique(),
+            user_transfer_authority: Pubkey::new_unique(),
+            open_order_address: None,
+            quote_mint_to_referrer: None,
+            in_amount: 1000,
+        };
+
+        let (instruction, _) = jupiter_invariant.swap_instruction(&swap_params).unwrap();
+        let swap_leg_and_account_metas = jupiter_invariant
+            .get_swap_leg_and_account_metas(&swap_params)
+            .unwrap();
+        assert_eq!(instruction.program_id, invariant_types::ID);
+        assert_eq!(
+            instruction.accounts,
+            swap_leg_and_account_metas.account_metas
+        );
+
+        // discriminator, bool, u64, bool, u128
+        assert_eq!(instruction.data.len(), 8 + 1 + 8 + 1 + 16);
+        assert_eq!(
+            InvariantSwapArgs::discriminator(),
+            hash(b"global:swap").to_bytes()[..8]
+        );
+        assert_eq!(instruction.data[..8], InvariantSwapArgs::discriminator());
+        let args = InvariantSwapArgs::deserialize(&mut &instruction.data[8..]).unwrap();
+        assert_eq!(
+            args,
+            InvariantSwapArgs {
+                x_to_y: false,
+                amount: 1000,
+                by_amount_in: true,
+                sqrt_price_limit: jupiter_invariant
+                    .quote_to_invariant_params(&QuoteParams {
+                        in_amount: 1000,
+                        input_mint: builder.token_y,
+                        output_mint: builder.token_x,
+                    })
+                    .unwrap()
+                    .sqrt_price_limit
+                    .v,
+            }
+        );
+
+        jupiter_invariant.swap_mode = SwapMode::ExactOut;
+        // the amount of the exact output swap is the expected output amount
+        let (instruction, _) = jupiter_invariant.swap_instruction(&swap_params).unwrap();
+        assert!(jupiter_invariant
+            .get_swap_leg_and_account_metas(&swap_params)
+            .is_err());
+        let args = InvariantSwapArgs::deserialize(&mut &instruction.data[8..]).unwrap();
+        assert!(!args.by_amount_in);
+        assert_eq!(args.amount, 1000);
     }
 }
@@ -0,0 +1,306 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anchor_lang::prelude::Pubkey;
+use anyhow::{anyhow, bail, Context};
+use invariant_types::utils::get_pool_address;
+use jupiter_core::amm::{Amm, Quote, QuoteParams, SwapParams};
+use jupiter_invariant::{
+    fixtures::PoolSnapshot,
+    swap::{InvariantSwapResult, SwapMode, UiSwapAmounts},
+    JupiterInvariant,
+};
+use rust_decimal::prelude::ToPrimitive;
+use serde_json::json;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{instruction::AccountMeta, program_pack::Pack};
+
+const RPC_MAINNET_CLINET: &str = "https://api.mainnet-beta.solana.com";
+
+const USAGE: &str = "\
+Usage: invariant-quote amount=<u64> [options]
+
+Pool, one of:
+  pool=<address>
+  mint-x=<address> mint-y=<address> fee=<fee, 1e12 = 100%> spacing=<tick spacing>
+
+Options:
+  dir=x-to-y|y-to-x           swap direction (default: x-to-y)
+  mode=exact-in|exact-out     meaning of the amount (default: exact-in)
+  rpc=<url>                   RPC used for the refresh (default: mainnet)
+  snapshot=<path>             quote a recorded pool snapshot instead of the RPC
+  decimals-x=<u8> decimals-y=<u8>
+                              mint decimals, fetched from the RPC when not given
+  authority=<address> source=<address> destination=<address>
+                              accounts of the generated account metas
+  format=text|json            output format (default: text)";
+
+struct Args {
+    values: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse() -> anyhow::Result<Self> {
+        let values = std::env::args()
+            .skip(1)
+            .map(|arg| match arg.split_once('=') {
+                Some((key, value)) => Ok((key.to_string(), value.to_string())),
+                None => Err(anyhow!("invalid argument `{}`\n\n{}", arg, USAGE)),
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { values })
+    }
+
+    fn get<T: FromStr>(&self, key: &str) -> anyhow::Result<Option<T>>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.values
+            .get(key)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| anyhow!("invalid value of `{}`: {}", key, e))
+            })
+            .transpose()
+    }
+
+    fn required<T: FromStr>(&self, key: &str) -> anyhow::Result<T>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.get(key)?
+            .ok_or_else(|| anyhow!("missing `{}`\n\n{}", key, USAGE))
+    }
+
+    fn market_key(&self) -> anyhow::Result<Pubkey> {
+        if let Some(pool) = self.get("pool")? {
+            return Ok(pool);
+        }
+        let (mint_x, mint_y): (Pubkey, Pubkey) =
+            (self.required("mint-x")?, self.required("mint-y")?);
+        let (token_x, token_y) = match mint_x < mint_y {
+            true => (mint_x, mint_y),
+            false => (mint_y, mint_x),
+        };
+        Ok(get_pool_address(
+            token_x,
+            token_y,
+            self.required("fee")?,
+            self.required("spacing")?,
+        ))
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    if std::env::args().any(|arg| arg == "help" || arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let args = Args::parse()?;
+
+    let amount: u64 = args.required("amount")?;
+    let x_to_y = match args.get::<String>("dir")?.as_deref() {
+        None | Some("x-to-y") => true,
+        Some("y-to-x") => false,
+        Some(dir) => bail!("invalid direction `{}`", dir),
+    };
+    let swap_mode = match args.get::<String>("mode")?.as_deref() {
+        None | Some("exact-in") => SwapMode::ExactIn,
+        Some("exact-out") => SwapMode::ExactOut,
+        Some(mode) => bail!("invalid mode `{}`", mode),
+    };
+    let json_output = match args.get::<String>("format")?.as_deref() {
+        None | Some("text") => false,
+        Some("json") => true,
+        Some(format) => bail!("invalid format `{}`", format),
+    };
+
+    // two-phase refresh: the pool and the tickmap first, then the ticks around the price
+    let (jupiter_invariant, rpc) = match args.get::<String>("snapshot")? {
+        Some(path) => (PoolSnapshot::load(path)?.to_jupiter_invariant()?, None),
+        None => {
+            let rpc_url = args
+                .get("rpc")?
+                .unwrap_or_else(|| RPC_MAINNET_CLINET.to_string());
+            let rpc = RpcClient::new(rpc_url);
+            let snapshot = PoolSnapshot::record(&rpc, args.market_key()?)?;
+            (snapshot.to_jupiter_invariant()?, Some(rpc))
+        }
+    };
+
+    let (token_x, token_y) = (
+        jupiter_invariant.pool.token_x,
+        jupiter_invariant.pool.token_y,
+    );
+    let decimals = match (
+        args.get::<u8>("decimals-x")?,
+        args.get::<u8>("decimals-y")?,
+        &rpc,
+    ) {
+        (Some(decimals_x), Some(decimals_y), _) => Some((decimals_x, decimals_y)),
+        (_, _, Some(rpc)) => Some(fetch_decimals(rpc, &[token_x, token_y])?),
+        _ => None,
+    };
+    let (input_mint, output_mint) = jupiter_invariant.swap_mints(x_to_y);
+    let quote_params = QuoteParams {
+        in_amount: amount,
+        input_mint,
+        output_mint,
+    };
+
+    let quote = jupiter_invariant.quote_with_mode(&quote_params, swap_mode)?;
+    let simulation_params =
+        jupiter_invariant.quote_to_invariant_params_with_mode(&quote_params, swap_mode)?;
+    let swap_result = jupiter_invariant.simulate_invariant_swap(&simulation_params)?;
+    // the swap leg executes the quoted input as an exact input swap in both modes
+    let account_metas = jupiter_invariant
+        .get_swap_leg_and_account_metas(&SwapParams {
+            source_mint: input_mint,
+            destination_mint: output_mint,
+            user_source_token_account: args.get("source")?.unwrap_or_default(),
+            user_destination_token_account: args.get("destination")?.unwrap_or_default(),
+            user_transfer_authority: args.get("authority")?.unwrap_or_default(),
+            open_order_address: None,
+            quote_mint_to_referrer: None,
+            in_amount: quote.in_amount,
+        })
+        .map(|swap_leg_and_account_metas| swap_leg_and_account_metas.account_metas)
+        .map_err(|e| e.to_string());
+
+    // the quote holds the amounts of the same simulation
+    let ui_amounts = decimals
+        .map(|(decimals_x, decimals_y)| match x_to_y {
+            true => swap_result.ui_amounts(decimals_x, decimals_y),
+            false => swap_result.ui_amounts(decimals_y, decimals_x),
+        })
+        .transpose()?;
+    match json_output {
+        true => print_json(
+            &jupiter_invariant,
+            x_to_y,
+            &quote,
+            &swap_result,
+            ui_amounts,
+            &account_metas,
+        )?,
+        false => print_text(
+            &jupiter_invariant,
+            x_to_y,
+            &quote,
+            &swap_result,
+            ui_amounts,
+            &account_metas,
+        ),
+    }
+
+    Ok(())
+}
+
+fn fetch_decimals(rpc: &RpcClient, mints: &[Pubkey; 2]) -> anyhow::Result<(u8, u8)> {
+    let accounts = rpc.get_multiple_accounts(mints)?;
+    let decimals = mints
+        .iter()
+        .zip(accounts)
+        .map(|(mint, account)| {
+            let account = account.ok_or_else(|| anyhow!("mint {} not found", mint))?;
+            let mint_state = spl_token::state::Mint::unpack(&account.data)
+                .with_context(|| format!("invalid mint account {}", mint))?;
+            Ok(mint_state.decimals)
+        })
+        .collect::<anyhow::Result<Vec<u8>>>()?;
+    match decimals[..] {
+        [decimals_x, decimals_y] => Ok((decimals_x, decimals_y)),
+        _ => bail!("missing mint accounts"),
+    }
+}
+
+fn print_text(
+    jupiter_invariant: &JupiterInvariant,
+    x_to_y: bool,
+    quote: &Quote,
+    swap_result: &InvariantSwapResult,
+    ui_amounts: Option<UiSwapAmounts>,
+    account_metas: &Result<Vec<AccountMeta>, String>,
+) {
+    println!("pool: {}", jupiter_invariant.market_key);
+    println!("direction: {}", if x_to_y { "x to y" } else { "y to x" });
+    println!("insufficient liquidity: {}", quote.not_enough_liquidity);
+    match ui_amounts {
+        Some(ui_amounts) => {
+            println!(
+                "input amount: {} ({})",
+                ui_amounts.in_amount, quote.in_amount
+            );
+            println!(
+                "output amount: {} ({})",
+                ui_amounts.out_amount, quote.out_amount
+            );
+            println!(
+                "fee amount: {} ({})",
+                ui_amounts.fee_amount, quote.fee_amount
+            );
+        }
+        None => {
+            println!("input amount: {}", quote.in_amount);
+            println!("output amount: {}", quote.out_amount);
+            println!("fee amount: {}", quote.fee_amount);
+        }
+    }
+    println!(
+        "price impact: {:.6} %",
+        quote.price_impact_pct.to_f64().unwrap_or_default() * 100.0
+    );
+    println!("crossed ticks: {:?}", swap_result.crossed_ticks);
+    match account_metas {
+        Ok(account_metas) => {
+            println!("account metas:");
+            for meta in account_metas {
+                println!(
+                    "  {} signer: {} writable: {}",
+                    meta.pubkey, meta.is_signer, meta.is_writable
+                );
+            }
+        }
+        Err(err) => println!("cannot create account metas: {}", err),
+    }
+}
+
+fn print_json(
+    jupiter_invariant: &JupiterInvariant,
+    x_to_y: bool,
+    quote: &Quote,
+    swap_result: &InvariantSwapResult,
+    ui_amounts: Option<UiSwapAmounts>,
+    account_metas: &Result<Vec<AccountMeta>, String>,
+) -> anyhow::Result<()> {
+    let account_metas = match account_metas {
+        Ok(account_metas) => json!(account_metas
+            .iter()
+            .map(|meta| json!({
+                "pubkey": meta.pubkey.to_string(),
+                "isSigner": meta.is_signer,
+                "isWritable": meta.is_writable,
+            }))
+            .collect::<Vec<_>>()),
+        Err(err) => json!({ "error": err }),
+    };
+
+    let output = json!({
+        "pool": jupiter_invariant.market_key.to_string(),
+        "xToY": x_to_y,
+        "notEnoughLiquidity": quote.not_enough_liquidity,
+        "inAmount": quote.in_amount,
+        "outAmount": quote.out_amount,
+        "feeAmount": quote.fee_amount,
+        "uiAmounts": ui_amounts.map(|ui_amounts| json!({
+            "inAmount": ui_amounts.in_amount.to_string(),
+            "outAmount": ui_amounts.out_amount.to_string(),
+            "feeAmount": ui_amounts.fee_amount.to_string(),
+        })),
+        "priceImpactPct": quote.price_impact_pct.to_string(),
+        "crossedTicks": swap_result.crossed_ticks,
+        "accountMetas": account_metas,
+    });
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
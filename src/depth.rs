@@ -0,0 +1,232 @@
+use invariant_types::decimals::{Decimal, Liquidity, Price, U256};
+
+use crate::{error::InvariantError, tickmap::PriceDirection, JupiterInvariant};
+
+// price level at an initialized tick, prices are amounts of token y for one token x in UI units
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DepthLevel {
+    pub tick_index: i32,
+    pub sqrt_price: Price,
+    pub price: rust_decimal::Decimal,
+    // liquidity active between this level and the previous one, closer to the current price
+    pub liquidity: Liquidity,
+    // amount of token x (levels below the price) or token y (levels above the price) that moves
+    // the price from the current one to this level, fees excluded
+    pub cumulative_amount: u64,
+}
+
+// active liquidity of the fetched ticks window seen as an order book of token x priced in token y
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LiquidityDepth {
+    pub price: rust_decimal::Decimal,
+    pub liquidity: Liquidity,
+    // levels below the current price, reached by selling token x, from the closest one
+    pub bids: Vec<DepthLevel>,
+    // levels above the current price, reached by buying token x, from the closest one
+    pub asks: Vec<DepthLevel>,
+}
+
+impl JupiterInvariant {
+    // levels end at the first initialized tick without a fetched account
+    pub fn liquidity_depth(
+        &self,
+        decimals_x: u8,
+        decimals_y: u8,
+    ) -> Result<LiquidityDepth, InvariantError> {
+        Ok(LiquidityDepth {
+            price: Self::sqrt_price_to_price(self.pool.sqrt_price, decimals_x, decimals_y)?,
+            liquidity: self.pool.liquidity,
+            bids: self.depth_levels(PriceDirection::DOWN, decimals_x, decimals_y)?,
+            asks: self.depth_levels(PriceDirection::UP, decimals_x, decimals_y)?,
+        })
+    }
+
+    fn depth_levels(
+        &self,
+        direction: PriceDirection,
+        decimals_x: u8,
+        decimals_y: u8,
+    ) -> Result<Vec<DepthLevel>, InvariantError> {
+        let mut indexes = match direction {
+            PriceDirection::UP => {
+                self.find_closest_tick_indexes(self.ticks_window.up, PriceDirection::UP)?
+            }
+            PriceDirection::DOWN => {
+                self.find_closest_tick_indexes(self.ticks_window.down, PriceDirection::DOWN)?
+            }
+        };
+        if direction == PriceDirection::DOWN {
+            indexes.reverse();
+        }
+
+        let (mut sqrt_price, mut liquidity) = (self.pool.sqrt_price, self.pool.liquidity);
+        let mut cumulative_amount = 0u64;
+        let mut levels = Vec::with_capacity(indexes.len());
+        for tick_index in indexes {
+            let tick = match self.ticks.get(&self.tick_index_to_address(tick_index)) {
+                Some(tick) => tick,
+                None => break,
+            };
+            let amount = match direction {
+                PriceDirection::UP => Self::delta_y(sqrt_price, tick.sqrt_price, liquidity),
+                PriceDirection::DOWN => Self::delta_x(tick.sqrt_price, sqrt_price, liquidity),
+            }?;
+            cumulative_amount = cumulative_amount
+                .checked_add(amount)
+                .ok_or(InvariantError::MathOverflow("cumulative amount add"))?;
+            levels.push(DepthLevel {
+                tick_index,
+                sqrt_price: tick.sqrt_price,
+                price: Self::sqrt_price_to_price(tick.sqrt_price, decimals_x, decimals_y)?,
+                liquidity,
+                cumulative_amount,
+            });
+
+            // crossing the tick upwards adds a positive liquidity change, downwards subtracts it
+            let adds_liquidity = tick.sign == (direction == PriceDirection::UP);
+            liquidity = match adds_liquidity {
+                true => liquidity.checked_add(tick.liquidity_change),
+                false => liquidity.checked_sub(tick.liquidity_change),
+            }
+            .map_err(|_| InvariantError::MathOverflow("liquidity change"))?;
+            sqrt_price = tick.sqrt_price;
+        }
+
+        Ok(levels)
+    }
+
+    // amount of token x between the two prices, rounded up
+    // liquidity * (upper - lower) / (upper * lower)
+    fn delta_x(
+        lower_sqrt_price: Price,
+        upper_sqrt_price: Price,
+        liquidity: Liquidity,
+    ) -> Result<u64, InvariantError> {
+        let (lower, upper) = (
+            U256::from(lower_sqrt_price.get()),
+            U256::from(upper_sqrt_price.get()),
+        );
+        let denominator = lower
+            .checked_mul(upper)
+            .and_then(|denominator| {
+                denominator.checked_mul(U256::exp10(Liquidity::scale() as usize))
+            })
+            .ok_or(InvariantError::MathOverflow("delta x denominator"))?;
+        if denominator.is_zero() {
+            return Err(InvariantError::PriceOutOfRange);
+        }
+        let numerator = U256::from(liquidity.get())
+            .checked_mul(upper.saturating_sub(lower))
+            .and_then(|numerator| numerator.checked_mul(U256::exp10(Price::scale() as usize)))
+            .ok_or(InvariantError::MathOverflow("delta x numerator"))?;
+
+        Self::div_up_to_u64(numerator, denominator)
+    }
+
+    // amount of token y between the two prices, rounded up
+    // liquidity * (upper - lower)
+    fn delta_y(
+        lower_sqrt_price: Price,
+        upper_sqrt_price: Price,
+        liquidity: Liquidity,
+    ) -> Result<u64, InvariantError> {
+        let numerator = U256::from(liquidity.get())
+            .checked_mul(
+                U256::from(upper_sqrt_price.get())
+                    .saturating_sub(U256::from(lower_sqrt_price.get())),
+            )
+            .ok_or(InvariantError::MathOverflow("delta y numerator"))?;
+        let denominator = U256::exp10(Liquidity::scale() as usize + Price::scale() as usize);
+
+        Self::div_up_to_u64(numerator, denominator)
+    }
+
+    fn div_up_to_u64(numerator: U256, denominator: U256) -> Result<u64, InvariantError> {
+        let result = denominator
+            .checked_sub(U256::one())
+            .and_then(|rounding| numerator.checked_add(rounding))
+            .and_then(|numerator| numerator.checked_div(denominator))
+            .ok_or(InvariantError::MathOverflow("div up"))?;
+        match result > U256::from(u64::MAX) {
+            true => Err(InvariantError::ConversionError("token amount exceeds u64")),
+            false => Ok(result.as_u64()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use invariant_types::{
+        decimals::{Decimal, FixedPoint, Liquidity},
+        math::calculate_price_sqrt,
+    };
+
+    use crate::{error::InvariantError, test_utils::PoolBuilder, JupiterInvariant};
+
+    #[test]
+    fn test_liquidity_depth() {
+        let jupiter_invariant = PoolBuilder::new(10, FixedPoint::new(0))
+            .position(-100, 100, 1_000_000_000000)
+            .position(-20, 20, 1_000_000_000000)
+            .build()
+            .unwrap();
+        let depth = jupiter_invariant.liquidity_depth(6, 6).unwrap();
+
+        assert_eq!(depth.price, rust_decimal::Decimal::ONE);
+        assert_eq!(depth.liquidity, Liquidity::new(2_000_000_000000));
+        assert_eq!(
+            depth
+                .bids
+                .iter()
+                .map(|level| level.tick_index)
+                .collect::<Vec<_>>(),
+            vec![-20, -100]
+        );
+        assert_eq!(
+            depth
+                .asks
+                .iter()
+                .map(|level| level.tick_index)
+                .collect::<Vec<_>>(),
+            vec![20, 100]
+        );
+
+        // the closest levels are in the range of both positions
+        assert_eq!(depth.bids[0].liquidity, Liquidity::new(2_000_000_000000));
+        assert_eq!(depth.asks[0].liquidity, Liquidity::new(2_000_000_000000));
+        assert_eq!(depth.bids[1].liquidity, Liquidity::new(1_000_000_000000));
+        assert_eq!(depth.asks[1].liquidity, Liquidity::new(1_000_000_000000));
+
+        // prices fall below and rise above the current one
+        assert!(depth.bids[0].price < depth.price && depth.bids[1].price < depth.bids[0].price);
+        assert!(depth.asks[0].price > depth.price && depth.asks[1].price > depth.asks[0].price);
+
+        // 2 * 10^6 * (1.0001^10 - 1) ~ 1000 of token y to move the price to the tick 20
+        assert!((1000..1002).contains(&depth.asks[0].cumulative_amount));
+        assert!(depth.asks[1].cumulative_amount > depth.asks[0].cumulative_amount);
+        assert!((999..1001).contains(&depth.bids[0].cumulative_amount));
+        assert!(depth.bids[1].cumulative_amount > depth.bids[0].cumulative_amount);
+    }
+
+    #[test]
+    fn test_depth_extreme_liquidity() {
+        // prices of the extreme ticks of the program
+        let (lower, upper) = (calculate_price_sqrt(-221818), calculate_price_sqrt(221818));
+        let liquidity = Liquidity::new(u128::MAX);
+
+        // liquidity * (upper - lower) * 10^24 does not fit in U256
+        assert!(matches!(
+            JupiterInvariant::delta_x(lower, upper, liquidity),
+            Err(InvariantError::MathOverflow(_))
+        ));
+        // fits in U256, but not in the token amount
+        assert!(matches!(
+            JupiterInvariant::delta_y(lower, upper, liquidity),
+            Err(InvariantError::ConversionError(_))
+        ));
+        assert!(matches!(
+            JupiterInvariant::delta_x(lower, lower, Liquidity::new(0)),
+            Ok(0)
+        ));
+    }
+}
@@ -0,0 +1,54 @@
+use anchor_lang::prelude::Pubkey;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum InvariantError {
+    #[error("Account data not found: {0}")]
+    AccountNotFound(Pubkey),
+    #[error("Error deserializing account data of {key}: {reason}")]
+    DeserializationFailed { key: Pubkey, reason: String },
+    #[error("Account {key} is not a {expected} account, discriminator mismatch")]
+    InvalidDiscriminator { key: Pubkey, expected: &'static str },
+    #[error("Account {key} is owned by {owner} instead of the Invariant program")]
+    InvalidOwner { key: Pubkey, owner: Pubkey },
+    #[error("Invalid source or destination mint")]
+    InvalidMintPair,
+    #[error("Ticks accounts outdated")]
+    TicksAccountsOutdated,
+    #[error("Insufficient liquidity")]
+    InsufficientLiquidity,
+    #[error("Token account of the intermediate mint {0} not provided")]
+    MissingTokenAccount(Pubkey),
+    #[error("Swap legs execute exact input swaps, use swap_instruction for exact output swaps")]
+    ExactOutSwapLeg,
+    #[error("Compute units limit exceeded")]
+    ComputeUnitsLimitExceeded,
+    #[error("Math overflow: {0}")]
+    MathOverflow(&'static str),
+    #[error("Invariant math error: {0}")]
+    MathError(String),
+    #[error("Tick index {tick_index} is not a multiple of tick spacing {tick_spacing}")]
+    TickSpacingViolation { tick_index: i32, tick_spacing: u16 },
+    #[error("Price out of range")]
+    PriceOutOfRange,
+    #[error("Invalid sqrt price limit")]
+    InvalidPriceLimit,
+    #[error("Sqrt price limit reached, the swap leg cannot carry the limit")]
+    PriceLimitReached,
+    #[error("Price impact must be in range (0, 1)")]
+    InvalidPriceImpact,
+    #[error("Conversion error: {0}")]
+    ConversionError(&'static str),
+    #[error("Accounts observed between slots {min_slot} and {max_slot} exceed the span of {max_slot_span} slots")]
+    InconsistentSlots {
+        min_slot: u64,
+        max_slot: u64,
+        max_slot_span: u64,
+    },
+    #[error("Slot of the cached account {0} is unknown")]
+    MissingSlot(Pubkey),
+    #[error("RPC error: {0}")]
+    RpcError(String),
+    #[error("Fixture error: {0}")]
+    FixtureError(String),
+}
@@ -0,0 +1,336 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    str::FromStr,
+};
+
+use anchor_lang::prelude::Pubkey;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use invariant_types::ID;
+use jupiter_core::amm::{Amm, KeyedAccount, QuoteParams};
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::account::Account;
+
+use crate::{error::InvariantError, JupiterInvariant};
+
+// snapshot of the pool, tickmap and tick accounts with the quotes computed at recording time
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PoolSnapshot {
+    pub market_key: String,
+    // account address -> base64 encoded account data
+    pub accounts: BTreeMap<String, String>,
+    pub quotes: Vec<GoldenQuote>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GoldenQuote {
+    pub x_to_y: bool,
+    // requested amount, `in_amount` can be lower when the liquidity runs out
+    pub amount: u64,
+    pub in_amount: u64,
+    pub out_amount: u64,
+    pub fee_amount: u64,
+    pub not_enough_liquidity: bool,
+}
+
+impl PoolSnapshot {
+    pub const MAX_RECORDING_ROUNDS: usize = 5;
+
+    pub fn record(rpc: &RpcClient, market_key: Pubkey) -> Result<Self, InvariantError> {
+        let pool_account = rpc
+            .get_account(&market_key)
+            .map_err(|e| InvariantError::RpcError(e.to_string()))?;
+        let mut jupiter_invariant = JupiterInvariant::new_from_keyed_account(&KeyedAccount {
+            key: market_key,
+            account: pool_account,
+            params: None,
+        })?;
+
+        // the tickmap has to be fetched before the tick accounts can be determined
+        for _ in 0..Self::MAX_RECORDING_ROUNDS {
+            let accounts_to_update = jupiter_invariant.get_accounts_to_update();
+            let accounts_map = JupiterInvariant::fetch_accounts(rpc, accounts_to_update)?;
+            jupiter_invariant.update_accounts(&accounts_map)?;
+
+            if !jupiter_invariant.ticks_accounts_outdated()? {
+                return Ok(Self::from_accounts(market_key, &accounts_map));
+            }
+        }
+
+        Err(InvariantError::TicksAccountsOutdated)
+    }
+
+    pub fn from_accounts(market_key: Pubkey, accounts_map: &HashMap<Pubkey, Vec<u8>>) -> Self {
+        let accounts = accounts_map
+            .iter()
+            .map(|(key, data)| (key.to_string(), STANDARD.encode(data)))
+            .collect();
+
+        Self {
+            market_key: market_key.to_string(),
+            accounts,
+            quotes: Vec::new(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, InvariantError> {
+        let content =
+            fs::read_to_string(path).map_err(|e| InvariantError::FixtureError(e.to_string()))?;
+        serde_json::from_str(&content).map_err(|e| InvariantError::FixtureError(e.to_string()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), InvariantError> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| InvariantError::FixtureError(e.to_string()))?;
+        fs::write(path, content).map_err(|e| InvariantError::FixtureError(e.to_string()))
+    }
+
+    pub fn market_key(&self) -> Result<Pubkey, InvariantError> {
+        Self::parse_pubkey(&self.market_key)
+    }
+
+    pub fn accounts_map(&self) -> Result<HashMap<Pubkey, Vec<u8>>, InvariantError> {
+        self.accounts
+            .iter()
+            .map(|(key, data)| {
+                let data = STANDARD
+                    .decode(data)
+                    .map_err(|e| InvariantError::FixtureError(e.to_string()))?;
+                Ok((Self::parse_pubkey(key)?, data))
+            })
+            .collect()
+    }
+
+    // replays the recorded accounts through `JupiterInvariant::update`
+    pub fn to_jupiter_invariant(&self) -> Result<JupiterInvariant, InvariantError> {
+        let market_key = self.market_key()?;
+        let accounts_map = self.accounts_map()?;
+        let pool_data = accounts_map
+            .get(&market_key)
+            .ok_or(InvariantError::AccountNotFound(market_key))?;
+
+        let mut jupiter_invariant = JupiterInvariant::new_from_keyed_account(&KeyedAccount {
+            key: market_key,
+            account: Account {
+                data: pool_data.clone(),
+                owner: ID,
+                ..Account::default()
+            },
+            params: None,
+        })?;
+        jupiter_invariant.update_accounts(&accounts_map)?;
+
+        Ok(jupiter_invariant)
+    }
+
+    // quotes both directions for every amount and stores them as the expected outputs
+    pub fn record_quotes(&mut self, in_amounts: &[u64]) -> Result<(), InvariantError> {
+        let jupiter_invariant = self.to_jupiter_invariant()?;
+        self.quotes = in_amounts
+            .iter()
+            .flat_map(|in_amount| [(true, *in_amount), (false, *in_amount)])
+            .map(|(x_to_y, amount)| Self::quote(&jupiter_invariant, x_to_y, amount))
+            .collect::<Result<Vec<GoldenQuote>, InvariantError>>()?;
+
+        Ok(())
+    }
+
+    pub fn quote(
+        jupiter_invariant: &JupiterInvariant,
+        x_to_y: bool,
+        amount: u64,
+    ) -> Result<GoldenQuote, InvariantError> {
+        let (input_mint, output_mint) = jupiter_invariant.swap_mints(x_to_y);
+        let quote = jupiter_invariant.quote_with_mode(
+            &QuoteParams {
+                in_amount: amount,
+                input_mint,
+                output_mint,
+            },
+            jupiter_invariant.swap_mode,
+        )?;
+
+        Ok(GoldenQuote {
+            x_to_y,
+            amount,
+            in_amount: quote.in_amount,
+            out_amount: quote.out_amount,
+            fee_amount: quote.fee_amount,
+            not_enough_liquidity: quote.not_enough_liquidity,
+        })
+    }
+
+    fn parse_pubkey(key: &str) -> Result<Pubkey, InvariantError> {
+        Pubkey::from_str(key).map_err(|e| InvariantError::FixtureError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use anchor_lang::prelude::Pubkey;
+    use invariant_types::{
+        decimals::{Decimal, FixedPoint},
+        utils::get_pool_address,
+    };
+    use solana_client::rpc_client::RpcClient;
+    use solana_sdk::pubkey;
+
+    use crate::{fixtures::PoolSnapshot, test_utils::PoolBuilder};
+
+    const RPC_MAINNET_CLINET: &str = "https://api.mainnet-beta.solana.com";
+
+    fn fixtures_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+    }
+
+    // pool shapes generated by `test_generate_fixtures`, all of them have to be committed
+    const SYNTHETIC_FIXTURES: [&str; 4] = ["stable", "volatile", "sparse", "empty_liquidity"];
+
+    #[test]
+    fn test_replay_fixtures() {
+        let paths = fs::read_dir(fixtures_dir())
+            .expect("missing fixtures directory, run test_generate_fixtures")
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+            .collect::<Vec<_>>();
+        for name in SYNTHETIC_FIXTURES {
+            assert!(
+                paths.contains(&fixtures_dir().join(format!("{}.json", name))),
+                "missing {} fixture, run test_generate_fixtures",
+                name
+            );
+        }
+
+        for path in paths {
+            let snapshot = PoolSnapshot::load(&path).unwrap();
+            let jupiter_invariant = snapshot.to_jupiter_invariant().unwrap();
+
+            assert!(!snapshot.quotes.is_empty(), "{:?}", path);
+            assert!(
+                !jupiter_invariant.ticks_accounts_outdated().unwrap(),
+                "{:?}",
+                path
+            );
+            for golden_quote in &snapshot.quotes {
+                let quote = PoolSnapshot::quote(
+                    &jupiter_invariant,
+                    golden_quote.x_to_y,
+                    golden_quote.amount,
+                )
+                .unwrap();
+                assert_eq!(&quote, golden_quote, "{:?}", path);
+            }
+        }
+    }
+
+    // fixed keys keep the generated accounts identical between runs
+    fn synthetic_pool(seed: u8, tick_spacing: u16, fee: FixedPoint) -> PoolBuilder {
+        PoolBuilder {
+            market_key: Pubkey::new_from_array([seed; 32]),
+            tickmap_key: Pubkey::new_from_array([seed + 1; 32]),
+            ..PoolBuilder::new(tick_spacing, fee).mints(
+                Pubkey::new_from_array([seed + 2; 32]),
+                Pubkey::new_from_array([seed + 3; 32]),
+            )
+        }
+    }
+
+    #[ignore = "overwrites the synthetic fixtures replayed by test_replay_fixtures"]
+    #[test]
+    fn test_generate_fixtures() {
+        let pools = [
+            // 0.01% fee, narrow ranges around the peg
+            (
+                "stable",
+                synthetic_pool(10, 1, FixedPoint::new(100000000))
+                    .position(-10, 10, 5_000_000_000_000000)
+                    .position(-100, 100, 1_000_000_000_000000)
+                    .position(-1000, 1000, 100_000_000_000000),
+            ),
+            // 1% fee, overlapping wide ranges with the price away from the middle
+            (
+                "volatile",
+                synthetic_pool(20, 100, FixedPoint::new(10000000000))
+                    .current_tick_index(300)
+                    .position(-20000, 15000, 10_000_000_000000)
+                    .position(-500, 800, 50_000_000_000000)
+                    .position(200, 5000, 20_000_000_000000),
+            ),
+            // initialized ticks beyond the tickmap search range, crossed virtually
+            (
+                "sparse",
+                synthetic_pool(30, 10, FixedPoint::new(3000000000))
+                    .position(-8000, -6000, 1_000_000_000000)
+                    .position(-100, 4000, 1_000_000_000000)
+                    .position(6000, 9000, 1_000_000_000000),
+            ),
+            // the price is outside of the only position
+            (
+                "empty_liquidity",
+                synthetic_pool(40, 10, FixedPoint::new(100000000)).position(
+                    100,
+                    200,
+                    1_000_000_000000,
+                ),
+            ),
+        ];
+
+        fs::create_dir_all(fixtures_dir()).unwrap();
+        for (name, builder) in pools {
+            let mut snapshot =
+                PoolSnapshot::from_accounts(builder.market_key, &builder.accounts_map().unwrap());
+            snapshot
+                .record_quotes(&[10u64.pow(3), 10u64.pow(6), 10u64.pow(9), 10u64.pow(12)])
+                .unwrap();
+            snapshot
+                .save(fixtures_dir().join(format!("{}.json", name)))
+                .unwrap();
+        }
+    }
+
+    #[ignore = "requires mainnet RPC, records the fixtures replayed by test_replay_fixtures"]
+    #[test]
+    fn test_record_fixtures() {
+        let rpc_url = std::env::args()
+            .filter(|arg| arg.starts_with("rpc="))
+            .map(|arg| arg.split_at(4).1.to_string())
+            .next()
+            .unwrap_or_else(|| RPC_MAINNET_CLINET.to_string());
+        let rpc = RpcClient::new(rpc_url);
+        let markets = [
+            (
+                "usdc_usdt",
+                pubkey!("BRt1iVYDNoohkL1upEb8UfHE8yji6gEDAmuN9Y4yekyc"),
+                [10u64.pow(3), 10u64.pow(6), 10u64.pow(9), 10u64.pow(12)],
+            ),
+            (
+                "usdc_wsol",
+                pubkey!("3vRuk97EaKACp1Z337PvVWNdab57hbDwefdi1zoUg46D"),
+                [10u64.pow(3), 10u64.pow(6), 10u64.pow(9), 10u64.pow(12)],
+            ),
+            (
+                "msol_wsol",
+                get_pool_address(
+                    pubkey!("mSoLzYCxHdYgdzU16g5QSh3i5K3z3KZK7ytfqcJm7So"),
+                    pubkey!("So11111111111111111111111111111111111111112"),
+                    100000000,
+                    1,
+                ),
+                [10u64.pow(6), 10u64.pow(9), 10u64.pow(12), 10u64.pow(15)],
+            ),
+        ];
+
+        fs::create_dir_all(fixtures_dir()).unwrap();
+        for (name, market_key, in_amounts) in markets {
+            let mut snapshot = PoolSnapshot::record(&rpc, market_key).unwrap();
+            snapshot.record_quotes(&in_amounts).unwrap();
+            snapshot
+                .save(fixtures_dir().join(format!("{}.json", name)))
+                .unwrap();
+        }
+    }
+}
@@ -1,26 +1,60 @@
 #![cfg_attr(feature = "strict", deny(unused_results))]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use anchor_lang::prelude::*;
-use anyhow::Result;
-use invariant_types::structs::{Pool, Tick, Tickmap};
+use invariant_types::decimals::Price;
+use invariant_types::structs::{Pool, Tick, Tickmap, TICK_CROSSES_PER_IX};
 use invariant_types::ID;
 use jupiter::jupiter_override::{Swap, SwapLeg};
 use jupiter_core::amm::{
     Amm, KeyedAccount, Quote, QuoteParams, SwapLegAndAccountMetas, SwapParams,
 };
+use solana_sdk::account::Account;
+use solana_sdk::instruction::Instruction;
 
-use accounts::{InvariantSwapAccounts, InvariantSwapParams};
-use swap::InvariantSwapResult;
+use accounts::{InvariantSwapAccounts, InvariantSwapArgs, InvariantSwapParams};
+use error::InvariantError;
+use swap::{InvariantSimulationParams, InvariantSwapResult, SwapMode};
+use utiles::TickAddressCache;
 
 pub mod accounts;
+pub mod depth;
+pub mod error;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+pub mod refresh;
+pub mod registry;
+pub mod router;
+pub mod slot;
 pub mod swap;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
 mod tests;
+pub mod tickmap;
 pub mod utiles;
 
 pub type Ticks = HashMap<Pubkey, Tick>;
 
+// number of initialized ticks prefetched above and below the current tick
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TicksWindow {
+    pub up: usize,
+    pub down: usize,
+    // adds the closest tick against the swap direction to the swap accounts
+    pub opposite_tick: bool,
+}
+
+impl Default for TicksWindow {
+    fn default() -> Self {
+        Self {
+            up: TICK_CROSSES_PER_IX,
+            down: TICK_CROSSES_PER_IX,
+            opposite_tick: false,
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct JupiterInvariant {
     pub program_id: Pubkey,
@@ -29,121 +63,225 @@ pub struct JupiterInvariant {
     pub pool: Pool,
     pub tickmap: Tickmap,
     pub ticks: Ticks,
+    pub ticks_window: TicksWindow,
+    pub tick_addresses: TickAddressCache,
+    // in the exact output mode `Amm::quote` treats `QuoteParams::in_amount` as the expected output amount
+    pub swap_mode: SwapMode,
+    // whether `Amm::quote` reserves an account for the referral fee, enabled by default
+    pub is_referral: bool,
+    // slots at which the cached pool, tickmap and ticks were observed, `Amm::update` drops the slots it overwrites
+    pub slots: HashMap<Pubkey, u64>,
+    // quotes fail when the cached accounts span more slots than this or any of them has no slot
+    pub max_slot_span: Option<u64>,
 }
 
 impl JupiterInvariant {
-    pub fn new_from_keyed_account(keyed_account: &KeyedAccount) -> Result<Self> {
-        let pool = Self::deserialize::<Pool>(&keyed_account.account.data)?;
+    pub fn new_from_keyed_account(keyed_account: &KeyedAccount) -> Result<Self, InvariantError> {
+        let pool = Self::deserialize::<Pool>(&keyed_account.key, &keyed_account.account.data)?;
 
         Ok(Self {
             program_id: ID,
             label: String::from("Invariant"),
             market_key: keyed_account.key,
             pool,
+            is_referral: true,
             ..Default::default()
         })
     }
-}
 
-impl Amm for JupiterInvariant {
-    fn label(&self) -> String {
-        self.label.clone()
-    }
+    // same as `new_from_keyed_account`, but rejects a pool not owned by the Invariant program
+    pub fn new_from_keyed_account_checked(
+        keyed_account: &KeyedAccount,
+    ) -> Result<Self, InvariantError> {
+        let jupiter_invariant = Self::new_from_keyed_account(keyed_account)?;
+        jupiter_invariant.check_owner(&keyed_account.key, &keyed_account.account.owner)?;
 
-    fn key(&self) -> Pubkey {
-        self.market_key
+        Ok(jupiter_invariant)
     }
 
-    fn get_reserve_mints(&self) -> Vec<Pubkey> {
-        vec![self.pool.token_x, self.pool.token_y]
-    }
+    // same as `Amm::update`, but rejects accounts not owned by the Invariant program
+    pub fn update_with_accounts(
+        &mut self,
+        accounts: &HashMap<Pubkey, Account>,
+    ) -> Result<(), InvariantError> {
+        let accounts_map = accounts
+            .iter()
+            .map(|(key, account)| {
+                self.check_owner(key, &account.owner)?;
+                Ok((*key, account.data.clone()))
+            })
+            .collect::<Result<HashMap<Pubkey, Vec<u8>>, InvariantError>>()?;
 
-    fn get_accounts_to_update(&self) -> Vec<Pubkey> {
-        let mut ticks_addresses = self.get_ticks_addresses_around();
-        ticks_addresses.extend([self.market_key, self.pool.tickmap]);
-        ticks_addresses
+        self.update_accounts(&accounts_map)
     }
 
-    fn update(&mut self, accounts_map: &HashMap<Pubkey, Vec<u8>>) -> anyhow::Result<()> {
-        let market_account_data: &[u8] = accounts_map
+    // merges the accounts present in the map, so a single changed account is enough to update the state
+    fn update_accounts(
+        &mut self,
+        accounts_map: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<(), InvariantError> {
+        let pool = accounts_map
             .get(&self.market_key)
-            .ok_or_else(|| anyhow::anyhow!("Market account data not found"))?;
-        let tickmap_account_data: &[u8] = accounts_map
+            .map(|data| Self::deserialize::<Pool>(&self.market_key, data))
+            .transpose()?;
+        let tickmap = accounts_map
             .get(&self.pool.tickmap)
-            .ok_or_else(|| anyhow::anyhow!("Tickmap account data not found"))?;
-
-        let pool = Self::deserialize::<Pool>(market_account_data)?;
-        let tickmap = Self::deserialize::<Tickmap>(tickmap_account_data)?;
+            .map(|data| Self::deserialize::<Tickmap>(&self.pool.tickmap, data))
+            .transpose()?;
 
-        let ticks = accounts_map
+        // closed tick accounts come with empty data
+        let (closed_ticks, ticks): (Vec<_>, Vec<_>) = accounts_map
             .iter()
             .filter(|(key, _)| !self.market_key.eq(key) && !self.pool.tickmap.eq(key))
+            .partition(|(_, data)| data.is_empty());
+        let ticks = ticks
+            .into_iter()
             .map(|(key, data)| {
-                let tick = Self::deserialize::<Tick>(data)?;
+                let tick = Self::deserialize::<Tick>(key, data)?;
                 Ok((*key, tick))
             })
-            .collect::<Result<Ticks>>()?;
+            .collect::<Result<Ticks, InvariantError>>()?;
 
-        self.ticks = ticks;
-        self.pool = pool;
-        self.tickmap = tickmap;
+        for key in accounts_map.keys() {
+            let _ = self.slots.remove(key);
+        }
+        if let Some(pool) = pool {
+            self.pool = pool;
+        }
+        if let Some(tickmap) = tickmap {
+            self.tickmap = tickmap;
+        }
+        for (key, _) in closed_ticks {
+            let _ = self.ticks.remove(key);
+        }
+        self.ticks.extend(ticks);
+        self.evict_ticks(accounts_map);
 
         Ok(())
     }
 
-    fn quote(&self, quote_params: &QuoteParams) -> anyhow::Result<Quote> {
-        let accounts_outdated = self.ticks_accounts_outdated();
-        if accounts_outdated {
-            return Ok(Quote {
-                not_enough_liquidity: true,
-                ..Quote::default()
-            });
-        }
-
-        let invariant_simulation_params = self.quote_to_invariant_params(quote_params)?;
-        let simulation_result = self.simulate_invariant_swap(&invariant_simulation_params);
-
-        match simulation_result {
-            Ok(result) => {
-                let not_enough_liquidity = result.is_not_enough_liquidity();
-                let InvariantSwapResult {
-                    in_amount,
-                    out_amount,
-                    fee_amount,
-                    starting_sqrt_price,
-                    ending_sqrt_price,
-                    ..
-                } = result;
-                let price_impact_pct =
-                    Self::calculate_price_impact(starting_sqrt_price, ending_sqrt_price)
-                        .unwrap_or_else(|_| rust_decimal::Decimal::default());
-
-                let quote = Quote {
-                    in_amount,
-                    out_amount,
-                    fee_amount,
-                    not_enough_liquidity,
-                    price_impact_pct,
-                    ..Quote::default()
-                };
-                Ok(quote)
+    // drops ticks that fell outside of the window around the current price, ticks of the current
+    // batch are kept as a tick initialized on chain can arrive before the tickmap marking it
+    fn evict_ticks(&mut self, accounts_map: &HashMap<Pubkey, Vec<u8>>) {
+        match self.get_ticks_addresses_around() {
+            Ok(ticks_addresses) => {
+                let window: HashSet<Pubkey> = ticks_addresses.into_iter().collect();
+                self.ticks
+                    .retain(|key, _| window.contains(key) || accounts_map.contains_key(key));
+                let (market_key, tickmap_key) = (self.market_key, self.pool.tickmap);
+                self.slots.retain(|key, _| {
+                    window.contains(key)
+                        || accounts_map.contains_key(key)
+                        || market_key.eq(key)
+                        || tickmap_key.eq(key)
+                });
+                let ticks = &self.ticks;
+                self.tick_addresses
+                    .retain(|address| window.contains(address) || ticks.contains_key(address));
             }
             Err(err) => {
-                println!("{}", err);
-                return Ok(Quote {
-                    not_enough_liquidity: true,
-                    ..Quote::default()
-                });
+                log::warn!("{}: cannot evict ticks: {}", self.market_key, err);
             }
         }
     }
 
-    fn get_swap_leg_and_account_metas(
+    // `Swap::Invariant` cannot carry the limit, so the leg is built only for amounts that do not
+    // reach it, partial fills are executed with `swap_instruction_with_sqrt_price_limit`
+    pub fn get_swap_leg_and_account_metas_with_sqrt_price_limit(
         &self,
         swap_params: &SwapParams,
-    ) -> anyhow::Result<SwapLegAndAccountMetas> {
+        sqrt_price_limit: Price,
+    ) -> Result<(SwapLegAndAccountMetas, InvariantSwapResult), InvariantError> {
+        let quote_params = QuoteParams {
+            in_amount: swap_params.in_amount,
+            input_mint: swap_params.source_mint,
+            output_mint: swap_params.destination_mint,
+        };
+        let invariant_simulation_params =
+            self.quote_to_invariant_params_with_sqrt_price_limit(&quote_params, sqrt_price_limit)?;
+        let (swap_leg_and_account_metas, invariant_swap_result) =
+            self.build_swap_leg_and_account_metas(swap_params, &invariant_simulation_params)?;
+        if invariant_swap_result.price_limit_reached {
+            return Err(InvariantError::PriceLimitReached);
+        }
+
+        Ok((swap_leg_and_account_metas, invariant_swap_result))
+    }
+
+    // instruction of the Invariant program swapping in the mode of the pool, the in amount of the
+    // swap params is the expected output amount in the exact output mode
+    pub fn swap_instruction(
+        &self,
+        swap_params: &SwapParams,
+    ) -> Result<(Instruction, InvariantSwapResult), InvariantError> {
+        let quote_params = QuoteParams {
+            in_amount: swap_params.in_amount,
+            input_mint: swap_params.source_mint,
+            output_mint: swap_params.destination_mint,
+        };
+        let invariant_simulation_params =
+            self.quote_to_invariant_params_with_mode(&quote_params, self.swap_mode)?;
+
+        self.build_swap_instruction(swap_params, &invariant_simulation_params)
+    }
+
+    pub fn swap_instruction_with_sqrt_price_limit(
+        &self,
+        swap_params: &SwapParams,
+        sqrt_price_limit: Price,
+    ) -> Result<(Instruction, InvariantSwapResult), InvariantError> {
+        let quote_params = QuoteParams {
+            in_amount: swap_params.in_amount,
+            input_mint: swap_params.source_mint,
+            output_mint: swap_params.destination_mint,
+        };
+        let invariant_simulation_params =
+            self.quote_to_invariant_params_with_sqrt_price_limit(&quote_params, sqrt_price_limit)?;
+
+        self.build_swap_instruction(swap_params, &invariant_simulation_params)
+    }
+
+    fn build_swap_instruction(
+        &self,
+        swap_params: &SwapParams,
+        invariant_simulation_params: &InvariantSimulationParams,
+    ) -> Result<(Instruction, InvariantSwapResult), InvariantError> {
+        let (invariant_swap_accounts, _, invariant_swap_result) =
+            self.build_swap_accounts(swap_params, invariant_simulation_params)?;
+        let instruction = invariant_swap_accounts.to_instruction(
+            self.program_id,
+            &InvariantSwapArgs::from(invariant_simulation_params),
+        );
+
+        Ok((instruction, invariant_swap_result))
+    }
+
+    fn build_swap_leg_and_account_metas(
+        &self,
+        swap_params: &SwapParams,
+        invariant_simulation_params: &InvariantSimulationParams,
+    ) -> Result<(SwapLegAndAccountMetas, InvariantSwapResult), InvariantError> {
+        let (invariant_swap_accounts, x_to_y, invariant_swap_result) =
+            self.build_swap_accounts(swap_params, invariant_simulation_params)?;
+        let account_metas = invariant_swap_accounts.to_account_metas();
+
+        Ok((
+            SwapLegAndAccountMetas {
+                swap_leg: SwapLeg::Swap {
+                    swap: Swap::Invariant { x_to_y },
+                },
+                account_metas,
+            },
+            invariant_swap_result,
+        ))
+    }
+
+    fn build_swap_accounts(
+        &self,
+        swap_params: &SwapParams,
+        invariant_simulation_params: &InvariantSimulationParams,
+    ) -> Result<(InvariantSwapAccounts, bool, InvariantSwapResult), InvariantError> {
         let SwapParams {
-            in_amount,
             destination_mint,
             source_mint,
             user_destination_token_account,
@@ -158,21 +296,16 @@ impl Amm for JupiterInvariant {
             _ => None,
         };
 
-        let quote_params = QuoteParams {
-            in_amount: *in_amount,
-            input_mint: *source_mint,
-            output_mint: *destination_mint,
-        };
-        let invariant_simulation_params = self.quote_to_invariant_params(&quote_params)?;
-        let invariant_swap_result = self
-            .simulate_invariant_swap(&invariant_simulation_params)
-            .map_err(|e| anyhow::anyhow!("Simulation error: {}", e))?;
+        let invariant_swap_result = self.simulate_invariant_swap(invariant_simulation_params)?;
 
         if invariant_swap_result.ticks_accounts_outdated {
-            return Err(anyhow::anyhow!("ticks accounts outdated"));
+            return Err(InvariantError::TicksAccountsOutdated);
+        }
+        if invariant_swap_result.global_insufficient_liquidity {
+            return Err(InvariantError::InsufficientLiquidity);
         }
-        if invariant_swap_result.is_not_enough_liquidity() {
-            return Err(anyhow::anyhow!("insufficient liquidity"));
+        if invariant_swap_result.is_exceeded_cu_referral(referral_fee.is_some()) {
+            return Err(InvariantError::ComputeUnitsLimitExceeded);
         }
 
         let invariant_swap_params = InvariantSwapParams {
@@ -187,14 +320,59 @@ impl Amm for JupiterInvariant {
 
         let (invariant_swap_accounts, x_to_y) =
             InvariantSwapAccounts::from_pubkeys(&self, &invariant_swap_params)?;
-        let account_metas = invariant_swap_accounts.to_account_metas();
 
-        Ok(SwapLegAndAccountMetas {
-            swap_leg: SwapLeg::Swap {
-                swap: Swap::Invariant { x_to_y },
-            },
-            account_metas,
-        })
+        Ok((invariant_swap_accounts, x_to_y, invariant_swap_result))
+    }
+}
+
+impl Amm for JupiterInvariant {
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+
+    fn key(&self) -> Pubkey {
+        self.market_key
+    }
+
+    fn get_reserve_mints(&self) -> Vec<Pubkey> {
+        vec![self.pool.token_x, self.pool.token_y]
+    }
+
+    fn get_accounts_to_update(&self) -> Vec<Pubkey> {
+        let mut ticks_addresses = self.get_ticks_addresses_around().unwrap_or_else(|err| {
+            log::warn!("{}: cannot get ticks addresses: {}", self.market_key, err);
+            Vec::new()
+        });
+        ticks_addresses.extend([self.market_key, self.pool.tickmap]);
+        ticks_addresses
+    }
+
+    fn update(&mut self, accounts_map: &HashMap<Pubkey, Vec<u8>>) -> anyhow::Result<()> {
+        Ok(self.update_accounts(accounts_map)?)
+    }
+
+    fn quote(&self, quote_params: &QuoteParams) -> anyhow::Result<Quote> {
+        Ok(self.quote_with_mode(quote_params, self.swap_mode)?)
+    }
+
+    fn get_swap_leg_and_account_metas(
+        &self,
+        swap_params: &SwapParams,
+    ) -> anyhow::Result<SwapLegAndAccountMetas> {
+        let quote_params = QuoteParams {
+            in_amount: swap_params.in_amount,
+            input_mint: swap_params.source_mint,
+            output_mint: swap_params.destination_mint,
+        };
+        // `Swap::Invariant` executes the whole amount as the input of the swap
+        if self.swap_mode == SwapMode::ExactOut {
+            return Err(InvariantError::ExactOutSwapLeg.into());
+        }
+        let invariant_simulation_params = self.quote_to_invariant_params(&quote_params)?;
+        let (swap_leg_and_account_metas, _) =
+            self.build_swap_leg_and_account_metas(swap_params, &invariant_simulation_params)?;
+
+        Ok(swap_leg_and_account_metas)
     }
 
     fn clone_amm(&self) -> Box<dyn Amm + Send + Sync> {
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use anchor_lang::prelude::Pubkey;
+#[cfg(feature = "async")]
+use invariant_types::structs::{Pool, Tickmap};
+#[cfg(feature = "async")]
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::account::Account;
+
+#[cfg(feature = "async")]
+use crate::error::InvariantError;
+use crate::JupiterInvariant;
+
+// summary of a refresh, the ticks are listed by their account addresses
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RefreshReport {
+    pub rounds: usize,
+    pub rpc_requests: usize,
+    pub fetched_accounts: usize,
+    pub pool_changed: bool,
+    pub tickmap_changed: bool,
+    pub added_ticks: Vec<Pubkey>,
+    pub updated_ticks: Vec<Pubkey>,
+    pub removed_ticks: Vec<Pubkey>,
+    // still outdated after the last allowed round
+    pub ticks_accounts_outdated: bool,
+}
+
+impl RefreshReport {
+    pub fn is_changed(&self) -> bool {
+        self.pool_changed
+            || self.tickmap_changed
+            || !self.added_ticks.is_empty()
+            || !self.updated_ticks.is_empty()
+            || !self.removed_ticks.is_empty()
+    }
+}
+
+impl JupiterInvariant {
+    // limit of the `getMultipleAccounts` RPC method
+    pub const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+    pub const MAX_REFRESH_ROUNDS: usize = 5;
+
+    pub fn diff(&self, previous: &JupiterInvariant) -> RefreshReport {
+        let (mut added_ticks, mut updated_ticks) = (Vec::new(), Vec::new());
+        for (address, tick) in &self.ticks {
+            match previous.ticks.get(address) {
+                None => added_ticks.push(*address),
+                Some(previous_tick) if previous_tick != tick => updated_ticks.push(*address),
+                Some(_) => {}
+            }
+        }
+        let removed_ticks = previous
+            .ticks
+            .keys()
+            .filter(|address| !self.ticks.contains_key(address))
+            .copied()
+            .collect();
+
+        RefreshReport {
+            pool_changed: previous.pool != self.pool,
+            tickmap_changed: previous.tickmap.bitmap != self.tickmap.bitmap,
+            added_ticks,
+            updated_ticks,
+            removed_ticks,
+            ..Default::default()
+        }
+    }
+
+    // fetches the pool and the tickmap first, then the ticks around the fetched price
+    #[cfg(feature = "async")]
+    pub async fn refresh(
+        &mut self,
+        rpc: &RpcClient,
+        max_rounds: usize,
+    ) -> Result<RefreshReport, InvariantError> {
+        let previous = self.clone();
+        let (mut rounds, mut rpc_requests, mut fetched_accounts) = (0, 0, 0);
+        // the pool and the tickmap are fetched again only when the previous round changed them
+        let mut fetch_pool_accounts = true;
+
+        while rounds < max_rounds {
+            rounds += 1;
+            let (mut accounts_map, ticks_addresses) = match fetch_pool_accounts {
+                true => {
+                    let pool_accounts = [self.market_key, self.pool.tickmap];
+                    let accounts_map =
+                        Self::fetch_accounts_nonblocking(rpc, &pool_accounts).await?;
+                    rpc_requests += pool_accounts.chunks(Self::MAX_MULTIPLE_ACCOUNTS).len();
+                    let market_account_data = accounts_map
+                        .get(&self.market_key)
+                        .ok_or(InvariantError::AccountNotFound(self.market_key))?;
+                    let tickmap_account_data = accounts_map
+                        .get(&self.pool.tickmap)
+                        .ok_or(InvariantError::AccountNotFound(self.pool.tickmap))?;
+                    let fetched_state = JupiterInvariant {
+                        program_id: self.program_id,
+                        market_key: self.market_key,
+                        tick_addresses: self.tick_addresses.clone(),
+                        pool: Self::deserialize::<Pool>(&self.market_key, market_account_data)?,
+                        tickmap: Self::deserialize::<Tickmap>(
+                            &self.pool.tickmap,
+                            tickmap_account_data,
+                        )?,
+                        ..Default::default()
+                    };
+                    let ticks_addresses = fetched_state.get_ticks_addresses_around()?;
+                    (accounts_map, ticks_addresses)
+                }
+                false => (HashMap::new(), self.get_ticks_addresses_around()?),
+            };
+
+            let ticks_accounts = Self::fetch_accounts_nonblocking(rpc, &ticks_addresses).await?;
+            rpc_requests += ticks_addresses.chunks(Self::MAX_MULTIPLE_ACCOUNTS).len();
+            accounts_map.extend(ticks_accounts);
+            fetched_accounts += accounts_map.len();
+
+            let (previous_pool, previous_bitmap) = (self.pool, self.tickmap.bitmap);
+            self.update_accounts(&accounts_map)?;
+            if !self.ticks_accounts_outdated()? {
+                break;
+            }
+            fetch_pool_accounts =
+                self.pool != previous_pool || self.tickmap.bitmap != previous_bitmap;
+        }
+
+        Ok(RefreshReport {
+            rounds,
+            rpc_requests,
+            fetched_accounts,
+            ticks_accounts_outdated: self.ticks_accounts_outdated()?,
+            ..self.diff(&previous)
+        })
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn fetch_accounts_nonblocking(
+        rpc: &RpcClient,
+        accounts_to_update: &[Pubkey],
+    ) -> Result<HashMap<Pubkey, Vec<u8>>, InvariantError> {
+        let mut accounts_map = HashMap::new();
+        for keys in accounts_to_update.chunks(Self::MAX_MULTIPLE_ACCOUNTS) {
+            let accounts = rpc
+                .get_multiple_accounts(keys)
+                .await
+                .map_err(|e| InvariantError::RpcError(e.to_string()))?;
+            Self::insert_fetched_accounts(&mut accounts_map, keys, accounts);
+        }
+        Ok(accounts_map)
+    }
+
+    pub(crate) fn insert_fetched_accounts(
+        accounts_map: &mut HashMap<Pubkey, Vec<u8>>,
+        keys: &[Pubkey],
+        accounts: Vec<Option<Account>>,
+    ) {
+        for (key, account) in keys.iter().zip(accounts) {
+            if let Some(account) = account {
+                let _ = accounts_map.insert(*key, account.data);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use anchor_lang::prelude::Pubkey;
+    use invariant_types::decimals::{Decimal, FixedPoint};
+    use solana_client::nonblocking::rpc_client::RpcClient;
+
+    use crate::{
+        test_utils::{MockRpcServer, PoolBuilder},
+        JupiterInvariant,
+    };
+
+    fn pool_builder() -> PoolBuilder {
+        PoolBuilder::new(10, FixedPoint::new(100000000))
+            .position(-100, 100, 1_000_000_000000)
+            .position(-20, 20, 1_000_000_000000)
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let builder = pool_builder();
+        let server = MockRpcServer::start(builder.accounts_map().unwrap());
+        let rpc = RpcClient::new(server.url());
+        let mut jupiter_invariant =
+            JupiterInvariant::new_from_keyed_account(&builder.keyed_account().unwrap()).unwrap();
+
+        // initial refresh fetches the ticks in the same round
+        {
+            let report = jupiter_invariant
+                .refresh(&rpc, JupiterInvariant::MAX_REFRESH_ROUNDS)
+                .await
+                .unwrap();
+            assert_eq!(report.rounds, 1);
+            assert_eq!(report.rpc_requests, 2);
+            assert_eq!(report.added_ticks.len(), 4);
+            assert!(report.tickmap_changed);
+            assert!(!report.ticks_accounts_outdated);
+            assert_eq!(server.requests(), vec![2, 4]);
+        }
+        // nothing changed
+        {
+            let report = jupiter_invariant
+                .refresh(&rpc, JupiterInvariant::MAX_REFRESH_ROUNDS)
+                .await
+                .unwrap();
+            assert!(!report.is_changed());
+        }
+        // position closed and the price moved
+        {
+            let mut builder = builder.clone().current_tick_index(30);
+            builder.ticks.clear();
+            let builder = builder.position(-100, 100, 1_000_000_000000);
+            server.set_accounts(builder.accounts_map().unwrap());
+
+            let report = jupiter_invariant
+                .refresh(&rpc, JupiterInvariant::MAX_REFRESH_ROUNDS)
+                .await
+                .unwrap();
+            assert!(report.pool_changed);
+            assert!(report.tickmap_changed);
+            assert_eq!(report.removed_ticks.len(), 2);
+            assert!(report.added_ticks.is_empty());
+            assert!(!jupiter_invariant.ticks_accounts_outdated().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_missing_tick() {
+        let builder = pool_builder();
+        let mut jupiter_invariant =
+            JupiterInvariant::new_from_keyed_account(&builder.keyed_account().unwrap()).unwrap();
+        let mut accounts = builder.accounts_map().unwrap();
+        let _ = accounts.remove(&jupiter_invariant.tick_index_to_address(20));
+        let server = MockRpcServer::start(accounts);
+        let rpc = RpcClient::new(server.url());
+
+        let report = jupiter_invariant.refresh(&rpc, 3).await.unwrap();
+        assert_eq!(report.rounds, 3);
+        assert!(report.ticks_accounts_outdated);
+        assert_eq!(report.added_ticks.len(), 3);
+        // the first round changes the tickmap, the pool is not fetched after the second one
+        assert_eq!(report.rpc_requests, 5);
+        assert_eq!(server.requests(), vec![2, 4, 2, 4, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_accounts_in_chunks() {
+        let builder = pool_builder();
+        let server = MockRpcServer::start(builder.accounts_map().unwrap());
+        let rpc = RpcClient::new(server.url());
+        let keys = [
+            vec![builder.market_key, builder.tickmap_key],
+            (0..248).map(|_| Pubkey::new_unique()).collect(),
+        ]
+        .concat();
+
+        let accounts_map = JupiterInvariant::fetch_accounts_nonblocking(&rpc, &keys)
+            .await
+            .unwrap();
+        assert_eq!(accounts_map.len(), 2);
+        assert_eq!(server.requests(), vec![100, 100, 50]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anchor_lang::prelude::Pubkey;
+use invariant_types::{structs::Pool, utils::get_pool_address, ID};
+use jupiter_core::amm::KeyedAccount;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::account::Account;
+
+use crate::{error::InvariantError, utiles::InvariantAccount, JupiterInvariant};
+
+// fee and tick spacing, both are seeds of the pool address
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FeeTier {
+    pub fee: u128,
+    pub tick_spacing: u16,
+}
+
+// all pools of the Invariant program indexed by their mint pair and fee tier
+#[derive(Clone, Debug, Default)]
+pub struct PoolRegistry {
+    pools: HashMap<Pubkey, Account>,
+    // (token x, token y) -> fee tier -> pool address
+    markets: HashMap<(Pubkey, Pubkey), BTreeMap<FeeTier, Pubkey>>,
+}
+
+impl PoolRegistry {
+    // `getProgramAccounts` filtered by the pool account discriminator
+    pub fn fetch(rpc: &RpcClient) -> Result<Self, InvariantError> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                0,
+                &Pool::discriminator(),
+            ))]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let accounts = rpc
+            .get_program_accounts_with_config(&ID, config)
+            .map_err(|e| InvariantError::RpcError(e.to_string()))?;
+
+        Ok(Self::from_accounts(accounts))
+    }
+
+    // accounts that are not pools derived from their own mints and fee tier are skipped
+    pub fn from_accounts(accounts: impl IntoIterator<Item = (Pubkey, Account)>) -> Self {
+        let mut registry = Self::default();
+        for (key, account) in accounts {
+            let pool = match JupiterInvariant::deserialize::<Pool>(&key, &account.data) {
+                Ok(pool) => pool,
+                Err(err) => {
+                    log::warn!("{}: skipping pool account: {}", key, err);
+                    continue;
+                }
+            };
+            let fee_tier = FeeTier {
+                fee: pool.fee.v,
+                tick_spacing: pool.tick_spacing,
+            };
+            let (token_x, token_y) = (pool.token_x, pool.token_y);
+            if get_pool_address(token_x, token_y, fee_tier.fee, fee_tier.tick_spacing) != key {
+                log::warn!("{}: skipping pool with a non canonical address", key);
+                continue;
+            }
+
+            let _ = registry
+                .markets
+                .entry((token_x, token_y))
+                .or_default()
+                .insert(fee_tier, key);
+            let _ = registry.pools.insert(key, account);
+        }
+        registry
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+
+    pub fn pool_addresses(&self) -> Vec<Pubkey> {
+        self.pools.keys().copied().collect()
+    }
+
+    // pools of the mint pair in any order, sorted by the fee tier
+    pub fn find_pools(&self, mint_a: Pubkey, mint_b: Pubkey) -> Vec<(FeeTier, Pubkey)> {
+        self.markets
+            .get(&Self::sorted_mints(mint_a, mint_b))
+            .map(|fee_tiers| {
+                fee_tiers
+                    .iter()
+                    .map(|(fee_tier, key)| (*fee_tier, *key))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn find_pool(&self, mint_a: Pubkey, mint_b: Pubkey, fee_tier: FeeTier) -> Option<Pubkey> {
+        let (token_x, token_y) = Self::sorted_mints(mint_a, mint_b);
+        let key = get_pool_address(token_x, token_y, fee_tier.fee, fee_tier.tick_spacing);
+        self.pools.contains_key(&key).then_some(key)
+    }
+
+    // the returned pool has to be refreshed before quoting, like one created from a keyed account
+    pub fn jupiter_invariant(&self, key: &Pubkey) -> Result<JupiterInvariant, InvariantError> {
+        let account = self
+            .pools
+            .get(key)
+            .ok_or(InvariantError::AccountNotFound(*key))?;
+
+        JupiterInvariant::new_from_keyed_account(&KeyedAccount {
+            key: *key,
+            account: account.clone(),
+            params: None,
+        })
+    }
+
+    pub fn jupiter_invariants(&self) -> Vec<JupiterInvariant> {
+        self.pools
+            .keys()
+            .filter_map(|key| match self.jupiter_invariant(key) {
+                Ok(jupiter_invariant) => Some(jupiter_invariant),
+                Err(err) => {
+                    log::warn!("{}: cannot create the pool: {}", key, err);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn sorted_mints(mint_a: Pubkey, mint_b: Pubkey) -> (Pubkey, Pubkey) {
+        match mint_a < mint_b {
+            true => (mint_a, mint_b),
+            false => (mint_b, mint_a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anchor_lang::prelude::Pubkey;
+    use invariant_types::{
+        decimals::{Decimal, FixedPoint},
+        utils::get_pool_address,
+    };
+    use solana_client::rpc_client::RpcClient;
+
+    use crate::{
+        registry::{FeeTier, PoolRegistry},
+        test_utils::{MockRpcServer, PoolBuilder},
+    };
+
+    fn canonical_pool(token_x: Pubkey, token_y: Pubkey, fee_tier: FeeTier) -> PoolBuilder {
+        PoolBuilder {
+            market_key: get_pool_address(token_x, token_y, fee_tier.fee, fee_tier.tick_spacing),
+            ..PoolBuilder::new(fee_tier.tick_spacing, FixedPoint::new(fee_tier.fee))
+                .mints(token_x, token_y)
+                .position(-100, 100, 1_000_000_000000)
+        }
+    }
+
+    #[test]
+    fn test_pool_registry() {
+        let mut mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+        mints.sort();
+        let (low_fee, high_fee) = (
+            FeeTier {
+                fee: 100000000,
+                tick_spacing: 1,
+            },
+            FeeTier {
+                fee: 3000000000,
+                tick_spacing: 10,
+            },
+        );
+
+        let mut accounts = HashMap::new();
+        for fee_tier in [high_fee, low_fee] {
+            accounts.extend(
+                canonical_pool(mints[0], mints[1], fee_tier)
+                    .accounts_map()
+                    .unwrap(),
+            );
+        }
+        // pool stored under an address not derived from its seeds
+        let unknown = PoolBuilder::new(1, FixedPoint::new(100000000));
+        accounts.extend(unknown.accounts_map().unwrap());
+
+        let server = MockRpcServer::start(accounts);
+        let registry = PoolRegistry::fetch(&RpcClient::new(server.url())).unwrap();
+
+        // tickmaps and ticks are filtered out by the discriminator
+        assert_eq!(registry.len(), 2);
+        assert!(registry
+            .find_pool(mints[0], mints[1], FeeTier::default())
+            .is_none());
+        let pools = registry.find_pools(mints[1], mints[0]);
+        assert_eq!(
+            pools
+                .iter()
+                .map(|(fee_tier, _)| *fee_tier)
+                .collect::<Vec<_>>(),
+            vec![low_fee, high_fee]
+        );
+        assert_eq!(
+            registry.find_pool(mints[1], mints[0], high_fee),
+            Some(pools[1].1)
+        );
+
+        let jupiter_invariant = registry.jupiter_invariant(&pools[0].1).unwrap();
+        assert_eq!(jupiter_invariant.pool.token_x, mints[0]);
+        assert_eq!({ jupiter_invariant.pool.tick_spacing }, 1);
+        assert_eq!(registry.jupiter_invariants().len(), 2);
+        assert!(registry.jupiter_invariant(&unknown.market_key).is_err());
+    }
+}
@@ -0,0 +1,745 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use anchor_lang::prelude::Pubkey;
+use jupiter_core::amm::{QuoteParams, SwapLegAndAccountMetas, SwapParams};
+
+use crate::{error::InvariantError, swap::SwapMode, JupiterInvariant};
+
+// part of the input routed through a single pool
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SplitLeg {
+    pub market_key: Pubkey,
+    pub in_amount: u64,
+    pub out_amount: u64,
+    pub fee_amount: u64,
+    pub price_impact_pct: rust_decimal::Decimal,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SplitQuote {
+    pub legs: Vec<SplitLeg>,
+    pub in_amount: u64,
+    pub out_amount: u64,
+    // the liquidity of all pools together ran out before the whole input was allocated
+    pub not_enough_liquidity: bool,
+}
+
+// single swap of a multi-hop route, the fee is paid in the input mint of the hop
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteHop {
+    pub market_key: Pubkey,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub in_amount: u64,
+    pub out_amount: u64,
+    pub fee_amount: u64,
+    pub price_impact_pct: rust_decimal::Decimal,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteQuote {
+    pub hops: Vec<RouteHop>,
+    pub in_amount: u64,
+    pub out_amount: u64,
+    // combined price impact of all hops
+    pub price_impact_pct: rust_decimal::Decimal,
+}
+
+pub struct SplitSwapLeg {
+    pub leg: SplitLeg,
+    pub swap_leg_and_account_metas: SwapLegAndAccountMetas,
+}
+
+// routes swaps through the given pools, either split across the fee tiers of a pair or through several hops
+pub struct Router<'a> {
+    pools: &'a [JupiterInvariant],
+    split_parts: usize,
+}
+
+impl<'a> Router<'a> {
+    pub const DEFAULT_SPLIT_PARTS: usize = 20;
+    pub const MAX_ROUTE_HOPS: usize = 3;
+
+    pub fn new(pools: &'a [JupiterInvariant]) -> Self {
+        Self {
+            pools,
+            split_parts: Self::DEFAULT_SPLIT_PARTS,
+        }
+    }
+
+    // granularity of the split, the input is allocated in this many equal parts
+    pub fn split_parts(mut self, split_parts: usize) -> Self {
+        self.split_parts = split_parts.max(1);
+        self
+    }
+
+    pub fn pair_pools(&self, mint_a: Pubkey, mint_b: Pubkey) -> Vec<&'a JupiterInvariant> {
+        self.pools
+            .iter()
+            .filter(|jupiter_invariant| {
+                let (token_x, token_y) = (
+                    jupiter_invariant.pool.token_x,
+                    jupiter_invariant.pool.token_y,
+                );
+                (token_x == mint_a && token_y == mint_b) || (token_x == mint_b && token_y == mint_a)
+            })
+            .collect()
+    }
+
+    // exact input split maximizing the output, every part goes to the pool with the best marginal output
+    pub fn quote_split(&self, quote_params: &QuoteParams) -> Result<SplitQuote, InvariantError> {
+        let pools = self.pair_pools(quote_params.input_mint, quote_params.output_mint);
+        if pools.is_empty() {
+            return Err(InvariantError::InvalidMintPair);
+        }
+
+        let amount = quote_params.in_amount;
+        let split_parts = (self.split_parts as u64).min(amount.max(1));
+        let part = amount / split_parts;
+        let ladder: Vec<u64> = (1..=split_parts).map(|index| index * part).collect();
+        // output of every pool for every multiple of the part, `None` when the liquidity runs out
+        // the split is exact input regardless of the swap mode the pools are configured with
+        let outputs: Vec<Vec<Option<u64>>> = pools
+            .iter()
+            .map(|jupiter_invariant| {
+                let x_to_y = quote_params.input_mint.eq(&jupiter_invariant.pool.token_x);
+                let quotes = match jupiter_invariant.quote_many_with_mode(
+                    &ladder,
+                    x_to_y,
+                    SwapMode::ExactIn,
+                ) {
+                    Ok(quotes) => quotes,
+                    Err(err) => {
+                        log::debug!("skipping pool {}: {}", jupiter_invariant.market_key, err);
+                        return Vec::new();
+                    }
+                };
+                quotes
+                    .into_iter()
+                    .map(|(_, quote)| (!quote.not_enough_liquidity).then_some(quote.out_amount))
+                    .collect()
+            })
+            .collect();
+
+        let mut allocated_parts = vec![0usize; pools.len()];
+        for _ in 0..split_parts {
+            let best = outputs
+                .iter()
+                .zip(&allocated_parts)
+                .enumerate()
+                .filter_map(|(index, (pool_outputs, allocated))| {
+                    let previous = match *allocated {
+                        0 => 0,
+                        allocated => pool_outputs[allocated - 1]?,
+                    };
+                    let next = (*pool_outputs.get(*allocated)?)?;
+                    Some((index, next.saturating_sub(previous), *allocated))
+                })
+                // ties go to the pool with fewer parts, then to the first one
+                .max_by_key(|(index, marginal, allocated)| {
+                    (*marginal, Reverse(*allocated), Reverse(*index))
+                });
+            match best {
+                Some((index, _, _)) => allocated_parts[index] += 1,
+                None => break,
+            }
+        }
+
+        // the remainder of the division into parts goes to the pool with the largest share
+        let mut amounts: Vec<u64> = allocated_parts
+            .iter()
+            .map(|allocated| *allocated as u64 * part)
+            .collect();
+        let all_parts_allocated = allocated_parts.iter().sum::<usize>() as u64 == split_parts;
+        if all_parts_allocated {
+            if let Some(largest) = (0..amounts.len()).max_by_key(|index| amounts[*index]) {
+                amounts[largest] += amount - part * split_parts;
+            }
+        }
+
+        let mut legs = Vec::new();
+        for (jupiter_invariant, in_amount) in pools.iter().zip(amounts) {
+            if in_amount == 0 {
+                continue;
+            }
+            let quote = jupiter_invariant.quote_with_mode(
+                &QuoteParams {
+                    in_amount,
+                    ..*quote_params
+                },
+                SwapMode::ExactIn,
+            )?;
+            legs.push(SplitLeg {
+                market_key: jupiter_invariant.market_key,
+                in_amount: quote.in_amount,
+                out_amount: quote.out_amount,
+                fee_amount: quote.fee_amount,
+                price_impact_pct: quote.price_impact_pct,
+            });
+        }
+
+        let in_amount = legs.iter().map(|leg| leg.in_amount).sum();
+        Ok(SplitQuote {
+            out_amount: legs.iter().map(|leg| leg.out_amount).sum(),
+            not_enough_liquidity: in_amount < amount,
+            in_amount,
+            legs,
+        })
+    }
+
+    // swap legs of the split, each with the amount allocated to its pool
+    pub fn split_swap_legs(
+        &self,
+        swap_params: &SwapParams,
+    ) -> Result<Vec<SplitSwapLeg>, InvariantError> {
+        let split_quote = self.quote_split(&QuoteParams {
+            in_amount: swap_params.in_amount,
+            input_mint: swap_params.source_mint,
+            output_mint: swap_params.destination_mint,
+        })?;
+        if split_quote.not_enough_liquidity {
+            return Err(InvariantError::InsufficientLiquidity);
+        }
+
+        split_quote
+            .legs
+            .into_iter()
+            .map(|leg| {
+                let jupiter_invariant = self.pool(&leg.market_key)?;
+                let leg_swap_params = Self::leg_swap_params(
+                    swap_params,
+                    (swap_params.source_mint, swap_params.destination_mint),
+                    (
+                        swap_params.user_source_token_account,
+                        swap_params.user_destination_token_account,
+                    ),
+                    leg.in_amount,
+                );
+                let swap_leg_and_account_metas =
+                    Self::swap_leg_and_account_metas(jupiter_invariant, &leg_swap_params)?;
+
+                Ok(SplitSwapLeg {
+                    leg,
+                    swap_leg_and_account_metas,
+                })
+            })
+            .collect()
+    }
+
+    // best route of up to `max_hops` pools, each hop swaps the whole output of the previous one
+    pub fn quote_route(
+        &self,
+        quote_params: &QuoteParams,
+        max_hops: usize,
+    ) -> Result<RouteQuote, InvariantError> {
+        let paths = self.find_paths(
+            quote_params.input_mint,
+            quote_params.output_mint,
+            max_hops.min(Self::MAX_ROUTE_HOPS),
+        );
+        if paths.is_empty() {
+            return Err(InvariantError::InvalidMintPair);
+        }
+
+        paths
+            .iter()
+            .filter_map(|path| {
+                match self.simulate_path(path, quote_params.input_mint, quote_params.in_amount) {
+                    Ok(route_quote) => Some(route_quote),
+                    Err(err) => {
+                        log::debug!("skipping route of {} hops: {}", path.len(), err);
+                        None
+                    }
+                }
+            })
+            // shorter routes win ties
+            .max_by_key(|route_quote| (route_quote.out_amount, Reverse(route_quote.hops.len())))
+            .ok_or(InvariantError::InsufficientLiquidity)
+    }
+
+    // the token accounts of the user for the mints between the hops are looked up by their mints
+    pub fn route_swap_legs(
+        &self,
+        swap_params: &SwapParams,
+        intermediate_token_accounts: &HashMap<Pubkey, Pubkey>,
+        max_hops: usize,
+    ) -> Result<(RouteQuote, Vec<SwapLegAndAccountMetas>), InvariantError> {
+        let route_quote = self.quote_route(
+            &QuoteParams {
+                in_amount: swap_params.in_amount,
+                input_mint: swap_params.source_mint,
+                output_mint: swap_params.destination_mint,
+            },
+            max_hops,
+        )?;
+        let token_account = |mint: &Pubkey| match mint {
+            mint if swap_params.source_mint.eq(mint) => Ok(swap_params.user_source_token_account),
+            mint if swap_params.destination_mint.eq(mint) => {
+                Ok(swap_params.user_destination_token_account)
+            }
+            mint => intermediate_token_accounts
+                .get(mint)
+                .copied()
+                .ok_or(InvariantError::MissingTokenAccount(*mint)),
+        };
+
+        let swap_legs_and_account_metas = route_quote
+            .hops
+            .iter()
+            .map(|hop| {
+                let jupiter_invariant = self.pool(&hop.market_key)?;
+                let hop_swap_params = Self::leg_swap_params(
+                    swap_params,
+                    (hop.input_mint, hop.output_mint),
+                    (
+                        token_account(&hop.input_mint)?,
+                        token_account(&hop.output_mint)?,
+                    ),
+                    hop.in_amount,
+                );
+                Self::swap_leg_and_account_metas(jupiter_invariant, &hop_swap_params)
+            })
+            .collect::<Result<Vec<_>, InvariantError>>()?;
+
+        Ok((route_quote, swap_legs_and_account_metas))
+    }
+
+    // paths of distinct pools that never return to an already visited mint
+    fn find_paths(
+        &self,
+        input_mint: Pubkey,
+        output_mint: Pubkey,
+        max_hops: usize,
+    ) -> Vec<Vec<&'a JupiterInvariant>> {
+        let mut paths = Vec::new();
+        let mut path = Vec::new();
+        let mut visited_mints = vec![input_mint];
+        self.extend_paths(
+            output_mint,
+            max_hops,
+            &mut path,
+            &mut visited_mints,
+            &mut paths,
+        );
+        paths
+    }
+
+    fn extend_paths(
+        &self,
+        output_mint: Pubkey,
+        max_hops: usize,
+        path: &mut Vec<&'a JupiterInvariant>,
+        visited_mints: &mut Vec<Pubkey>,
+        paths: &mut Vec<Vec<&'a JupiterInvariant>>,
+    ) {
+        if path.len() == max_hops {
+            return;
+        }
+        let mint = visited_mints[visited_mints.len() - 1];
+        for jupiter_invariant in self.pools {
+            let next_mint = match (
+                jupiter_invariant.pool.token_x,
+                jupiter_invariant.pool.token_y,
+            ) {
+                (token_x, token_y) if token_x == mint => token_y,
+                (token_x, token_y) if token_y == mint => token_x,
+                _ => continue,
+            };
+            if visited_mints.contains(&next_mint) {
+                continue;
+            }
+
+            path.push(jupiter_invariant);
+            if next_mint == output_mint {
+                paths.push(path.clone());
+            } else {
+                visited_mints.push(next_mint);
+                self.extend_paths(output_mint, max_hops, path, visited_mints, paths);
+                let _ = visited_mints.pop();
+            }
+            let _ = path.pop();
+        }
+    }
+
+    fn simulate_path(
+        &self,
+        path: &[&JupiterInvariant],
+        input_mint: Pubkey,
+        in_amount: u64,
+    ) -> Result<RouteQuote, InvariantError> {
+        let (mut hop_input_mint, mut hop_in_amount) = (input_mint, in_amount);
+        let mut hops = Vec::with_capacity(path.len());
+        for jupiter_invariant in path {
+            let x_to_y = hop_input_mint.eq(&jupiter_invariant.pool.token_x);
+            let (_, hop_output_mint) = jupiter_invariant.swap_mints(x_to_y);
+            if jupiter_invariant.ticks_accounts_outdated()? {
+                return Err(InvariantError::TicksAccountsOutdated);
+            }
+            let invariant_simulation_params =
+                jupiter_invariant.quote_to_invariant_params(&QuoteParams {
+                    in_amount: hop_in_amount,
+                    input_mint: hop_input_mint,
+                    output_mint: hop_output_mint,
+                })?;
+            let result = jupiter_invariant.simulate_invariant_swap(&invariant_simulation_params)?;
+            if result.is_not_enough_liquidity_referral(jupiter_invariant.is_referral) {
+                return Err(InvariantError::InsufficientLiquidity);
+            }
+
+            hops.push(RouteHop {
+                market_key: jupiter_invariant.market_key,
+                input_mint: hop_input_mint,
+                output_mint: hop_output_mint,
+                in_amount: result.in_amount,
+                out_amount: result.out_amount,
+                fee_amount: result.fee_amount,
+                price_impact_pct: JupiterInvariant::calculate_price_impact(
+                    result.starting_sqrt_price,
+                    result.ending_sqrt_price,
+                )?,
+            });
+            (hop_input_mint, hop_in_amount) = (hop_output_mint, result.out_amount);
+        }
+
+        // 1 - (1 - impact_1) * (1 - impact_2) * ...
+        let price_quote = hops.iter().fold(rust_decimal::Decimal::ONE, |quote, hop| {
+            quote * (rust_decimal::Decimal::ONE - hop.price_impact_pct)
+        });
+        Ok(RouteQuote {
+            in_amount: hops.first().map_or(0, |hop| hop.in_amount),
+            out_amount: hop_in_amount,
+            price_impact_pct: rust_decimal::Decimal::ONE - price_quote,
+            hops,
+        })
+    }
+
+    fn pool(&self, market_key: &Pubkey) -> Result<&'a JupiterInvariant, InvariantError> {
+        self.pools
+            .iter()
+            .find(|jupiter_invariant| jupiter_invariant.market_key.eq(market_key))
+            .ok_or(InvariantError::AccountNotFound(*market_key))
+    }
+
+    fn leg_swap_params(
+        swap_params: &SwapParams,
+        (source_mint, destination_mint): (Pubkey, Pubkey),
+        (source_account, destination_account): (Pubkey, Pubkey),
+        in_amount: u64,
+    ) -> SwapParams {
+        SwapParams {
+            source_mint,
+            destination_mint,
+            user_source_token_account: source_account,
+            user_destination_token_account: destination_account,
+            user_transfer_authority: swap_params.user_transfer_authority,
+            open_order_address: swap_params.open_order_address,
+            quote_mint_to_referrer: swap_params.quote_mint_to_referrer.clone(),
+            in_amount,
+        }
+    }
+
+    fn swap_leg_and_account_metas(
+        jupiter_invariant: &JupiterInvariant,
+        swap_params: &SwapParams,
+    ) -> Result<SwapLegAndAccountMetas, InvariantError> {
+        let invariant_simulation_params =
+            jupiter_invariant.quote_to_invariant_params(&QuoteParams {
+                in_amount: swap_params.in_amount,
+                input_mint: swap_params.source_mint,
+                output_mint: swap_params.destination_mint,
+            })?;
+        let (swap_leg_and_account_metas, _) = jupiter_invariant
+            .build_swap_leg_and_account_metas(swap_params, &invariant_simulation_params)?;
+
+        Ok(swap_leg_and_account_metas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anchor_lang::prelude::Pubkey;
+    use invariant_types::decimals::{Decimal, FixedPoint};
+    use jupiter_core::amm::{Amm, QuoteParams, SwapParams};
+
+    use crate::{
+        error::InvariantError, router::Router, slot::SlotAccountsMap, swap::SwapMode,
+        test_utils::PoolBuilder, JupiterInvariant,
+    };
+
+    fn pair_pool(token_x: Pubkey, token_y: Pubkey, fee: u128, liquidity: u128) -> JupiterInvariant {
+        PoolBuilder::new(10, FixedPoint::new(fee))
+            .mints(token_x, token_y)
+            .position(-10000, 10000, liquidity)
+            .build()
+            .unwrap()
+    }
+
+    fn quote_params_of(in_amount: u64, input_mint: Pubkey, output_mint: Pubkey) -> QuoteParams {
+        QuoteParams {
+            in_amount,
+            input_mint,
+            output_mint,
+        }
+    }
+
+    #[test]
+    fn test_quote_split() {
+        let (token_x, token_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let quote_params = QuoteParams {
+            in_amount: 40000,
+            input_mint: token_x,
+            output_mint: token_y,
+        };
+
+        // identical pools share the input equally
+        {
+            let pools = vec![
+                pair_pool(token_x, token_y, 100000000, 100_000_000000),
+                pair_pool(token_x, token_y, 100000000, 100_000_000000),
+            ];
+            let single_quote = pools[0].quote(&quote_params).unwrap();
+            let split_quote = Router::new(&pools).quote_split(&quote_params).unwrap();
+
+            assert_eq!(split_quote.legs.len(), 2);
+            assert_eq!(split_quote.legs[0].in_amount, 20000);
+            assert_eq!(split_quote.legs[1].in_amount, 20000);
+            assert_eq!(split_quote.in_amount, 40000);
+            assert!(!split_quote.not_enough_liquidity);
+            assert!(split_quote.out_amount > single_quote.out_amount);
+        }
+        // a cheaper and deeper tier takes the larger share
+        {
+            let pools = vec![
+                pair_pool(token_x, token_y, 3000000000, 100_000_000000),
+                pair_pool(token_x, token_y, 100000000, 400_000_000000),
+            ];
+            let best_single_out = pools
+                .iter()
+                .map(|pool| pool.quote(&quote_params).unwrap().out_amount)
+                .max()
+                .unwrap();
+            let split_quote = Router::new(&pools).quote_split(&quote_params).unwrap();
+
+            let deep_leg = split_quote
+                .legs
+                .iter()
+                .find(|leg| leg.market_key == pools[1].market_key)
+                .unwrap();
+            assert!(deep_leg.in_amount > 20000);
+            assert_eq!(split_quote.in_amount, 40000);
+            assert!(split_quote.out_amount >= best_single_out);
+        }
+        // no pool of the pair
+        {
+            let pools = vec![pair_pool(
+                token_x,
+                Pubkey::new_unique(),
+                100000000,
+                100_000_000000,
+            )];
+            assert!(Router::new(&pools).quote_split(&quote_params).is_err());
+        }
+    }
+
+    #[test]
+    fn test_quote_split_exact_out_pool() {
+        let (token_x, token_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let quote_params = quote_params_of(40000, token_x, token_y);
+        let exact_in_pools = vec![
+            pair_pool(token_x, token_y, 100000000, 100_000_000000),
+            pair_pool(token_x, token_y, 3000000000, 100_000_000000),
+        ];
+        // the same pools quoting exact outputs on their own
+        let exact_out_pools: Vec<JupiterInvariant> = exact_in_pools
+            .iter()
+            .map(|jupiter_invariant| JupiterInvariant {
+                swap_mode: SwapMode::ExactOut,
+                ..jupiter_invariant.clone()
+            })
+            .collect();
+
+        let expected = Router::new(&exact_in_pools)
+            .quote_split(&quote_params)
+            .unwrap();
+        let split_quote = Router::new(&exact_out_pools)
+            .quote_split(&quote_params)
+            .unwrap();
+        assert_eq!(split_quote, expected);
+        assert_eq!(split_quote.in_amount, 40000);
+        assert!(split_quote.out_amount < 40000);
+    }
+
+    #[test]
+    fn test_quote_split_stale_pool() {
+        let (token_x, token_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let quote_params = quote_params_of(40000, token_x, token_y);
+        // the pool account is far ahead of the ticks it was fetched with
+        let builder = PoolBuilder::new(10, FixedPoint::new(100000000))
+            .mints(token_x, token_y)
+            .position(-10000, 10000, 100_000_000000);
+        let mut stale = builder.build().unwrap();
+        let accounts: SlotAccountsMap = builder
+            .accounts_map()
+            .unwrap()
+            .into_iter()
+            .map(|(key, data)| match key == builder.market_key {
+                true => (key, (500, data)),
+                false => (key, (100, data)),
+            })
+            .collect();
+        let _ = stale.update_with_slots(&accounts).unwrap();
+        stale.max_slot_span = Some(10);
+        let pools = vec![
+            stale,
+            pair_pool(token_x, token_y, 3000000000, 100_000_000000),
+        ];
+        assert!(pools[0].quote(&quote_params).is_err());
+
+        let split_quote = Router::new(&pools).quote_split(&quote_params).unwrap();
+        assert_eq!(split_quote.legs.len(), 1);
+        assert_eq!(split_quote.legs[0].market_key, pools[1].market_key);
+        assert_eq!(split_quote.in_amount, 40000);
+        assert!(!split_quote.not_enough_liquidity);
+    }
+
+    #[test]
+    fn test_split_swap_legs() {
+        let (token_x, token_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let pools = vec![
+            pair_pool(token_x, token_y, 100000000, 100_000_000000),
+            pair_pool(token_x, token_y, 100000000, 100_000_000000),
+        ];
+        let swap_legs = Router::new(&pools)
+            .split_swap_legs(&SwapParams {
+                source_mint: token_y,
+                destination_mint: token_x,
+                user_source_token_account: Pubkey::new_unique(),
+                user_destination_token_account: Pubkey::new_unique(),
+                user_transfer_authority: Pubkey::new_unique(),
+                open_order_address: None,
+                quote_mint_to_referrer: None,
+                in_amount: 30001,
+            })
+            .unwrap();
+
+        assert_eq!(swap_legs.len(), 2);
+        assert_eq!(
+            swap_legs
+                .iter()
+                .map(|swap_leg| swap_leg.leg.in_amount)
+                .sum::<u64>(),
+            30001
+        );
+        for (swap_leg, jupiter_invariant) in swap_legs.iter().zip(&pools) {
+            assert_eq!(swap_leg.leg.market_key, jupiter_invariant.market_key);
+            assert!(swap_leg
+                .swap_leg_and_account_metas
+                .account_metas
+                .iter()
+                .any(|meta| meta.pubkey == jupiter_invariant.market_key));
+        }
+    }
+
+    #[test]
+    fn test_quote_route() {
+        // msol -> sol -> usdc through deep pools, or directly through a shallow one
+        let (msol, sol, usdc) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let pools = vec![
+            pair_pool(msol, sol, 100000000, 1_000_000_000000),
+            pair_pool(usdc, sol, 100000000, 1_000_000_000000),
+            pair_pool(msol, usdc, 100000000, 10_000_000000),
+        ];
+        let router = Router::new(&pools);
+        let quote_params = QuoteParams {
+            in_amount: 5000,
+            input_mint: msol,
+            output_mint: usdc,
+        };
+
+        // direct pool only
+        {
+            let route_quote = router.quote_route(&quote_params, 1).unwrap();
+            assert_eq!(route_quote.hops.len(), 1);
+            assert_eq!(route_quote.hops[0].market_key, pools[2].market_key);
+        }
+        // two hops chained through sol
+        {
+            let route_quote = router.quote_route(&quote_params, 3).unwrap();
+            assert_eq!(
+                route_quote
+                    .hops
+                    .iter()
+                    .map(|hop| hop.market_key)
+                    .collect::<Vec<_>>(),
+                vec![pools[0].market_key, pools[1].market_key]
+            );
+            let first_hop = pools[0].quote(&quote_params_of(5000, msol, sol)).unwrap();
+            let second_hop = pools[1]
+                .quote(&quote_params_of(first_hop.out_amount, sol, usdc))
+                .unwrap();
+            assert_eq!(route_quote.hops[0].output_mint, sol);
+            assert_eq!(route_quote.hops[1].in_amount, first_hop.out_amount);
+            assert_eq!(route_quote.out_amount, second_hop.out_amount);
+            assert_eq!(route_quote.in_amount, 5000);
+            assert!(route_quote.price_impact_pct > route_quote.hops[0].price_impact_pct);
+            assert!(route_quote.price_impact_pct > route_quote.hops[1].price_impact_pct);
+        }
+        // no route
+        {
+            let unknown_mint = Pubkey::new_unique();
+            let result = router.quote_route(&quote_params_of(5000, msol, unknown_mint), 3);
+            assert_eq!(result, Err(InvariantError::InvalidMintPair));
+        }
+    }
+
+    #[test]
+    fn test_route_swap_legs() {
+        let (msol, sol, usdc) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let pools = vec![
+            pair_pool(msol, sol, 100000000, 1_000_000_000000),
+            pair_pool(sol, usdc, 100000000, 1_000_000_000000),
+        ];
+        let swap_params = SwapParams {
+            source_mint: msol,
+            destination_mint: usdc,
+            user_source_token_account: Pubkey::new_unique(),
+            user_destination_token_account: Pubkey::new_unique(),
+            user_transfer_authority: Pubkey::new_unique(),
+            open_order_address: None,
+            quote_mint_to_referrer: None,
+            in_amount: 5000,
+        };
+        let sol_account = Pubkey::new_unique();
+        let router = Router::new(&pools);
+
+        let (route_quote, swap_legs_and_account_metas) = router
+            .route_swap_legs(&swap_params, &HashMap::from([(sol, sol_account)]), 3)
+            .unwrap();
+        assert_eq!(route_quote.hops.len(), 2);
+        assert_eq!(swap_legs_and_account_metas.len(), 2);
+        for (swap_leg_and_account_metas, jupiter_invariant) in
+            swap_legs_and_account_metas.iter().zip(&pools)
+        {
+            let keys: Vec<Pubkey> = swap_leg_and_account_metas
+                .account_metas
+                .iter()
+                .map(|meta| meta.pubkey)
+                .collect();
+            assert!(keys.contains(&jupiter_invariant.market_key));
+            assert!(keys.contains(&sol_account));
+        }
+
+        let result = router.route_swap_legs(&swap_params, &HashMap::new(), 3);
+        assert_eq!(result.err(), Some(InvariantError::MissingTokenAccount(sol)));
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use anchor_lang::prelude::Pubkey;
+
+use crate::{error::InvariantError, JupiterInvariant};
+
+// account address -> (slot at which the account was observed, account data)
+pub type SlotAccountsMap = HashMap<Pubkey, (u64, Vec<u8>)>;
+
+impl JupiterInvariant {
+    // applies slot-tagged accounts, writes older than the cached ones are rejected and returned
+    pub fn update_with_slots(
+        &mut self,
+        accounts: &SlotAccountsMap,
+    ) -> Result<Vec<Pubkey>, InvariantError> {
+        let (accepted, rejected): (Vec<_>, Vec<_>) = accounts
+            .iter()
+            .partition(|(key, (slot, _))| self.slot(key).map_or(true, |cached| *slot >= cached));
+        let accounts_map: HashMap<Pubkey, Vec<u8>> = accepted
+            .iter()
+            .map(|(key, (_, data))| (**key, data.clone()))
+            .collect();
+
+        self.update_accounts(&accounts_map)?;
+        for (key, (slot, _)) in accepted {
+            if self.is_cached(key) {
+                let _ = self.slots.insert(*key, *slot);
+            }
+        }
+
+        let rejected: Vec<Pubkey> = rejected.into_iter().map(|(key, _)| *key).collect();
+        if !rejected.is_empty() {
+            log::debug!(
+                "{}: rejected {} outdated accounts",
+                self.market_key,
+                rejected.len()
+            );
+        }
+        Ok(rejected)
+    }
+
+    pub fn slot(&self, key: &Pubkey) -> Option<u64> {
+        self.slots.get(key).copied()
+    }
+
+    fn is_cached(&self, key: &Pubkey) -> bool {
+        self.market_key.eq(key) || self.pool.tickmap.eq(key) || self.ticks.contains_key(key)
+    }
+
+    // lowest and highest slot among the cached pool, tickmap and ticks, accounts without a slot are skipped
+    pub fn slot_range(&self) -> Option<(u64, u64)> {
+        [self.market_key, self.pool.tickmap]
+            .iter()
+            .chain(self.ticks.keys())
+            .filter_map(|key| self.slot(key))
+            .fold(None, |range, slot| match range {
+                None => Some((slot, slot)),
+                Some((min_slot, max_slot)) => Some((min_slot.min(slot), max_slot.max(slot))),
+            })
+    }
+
+    // every cached account needs a slot, `Amm::update` clears the slots of the accounts it writes
+    pub fn check_slot_consistency(&self, max_slot_span: u64) -> Result<(), InvariantError> {
+        if let Some(key) = [self.market_key, self.pool.tickmap]
+            .iter()
+            .chain(self.ticks.keys())
+            .find(|key| self.slot(key).is_none())
+        {
+            return Err(InvariantError::MissingSlot(*key));
+        }
+
+        match self.slot_range() {
+            Some((min_slot, max_slot)) if max_slot - min_slot > max_slot_span => {
+                Err(InvariantError::InconsistentSlots {
+                    min_slot,
+                    max_slot,
+                    max_slot_span,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use invariant_types::decimals::{Decimal, FixedPoint};
+    use jupiter_core::amm::{Amm, QuoteParams};
+
+    use crate::{error::InvariantError, slot::SlotAccountsMap, test_utils::PoolBuilder};
+
+    #[test]
+    fn test_update_with_slots() {
+        let builder = PoolBuilder::new(10, FixedPoint::new(0))
+            .position(-100, 100, 1000)
+            .position(-20, 20, 500);
+        let accounts: SlotAccountsMap = builder
+            .accounts_map()
+            .unwrap()
+            .into_iter()
+            .map(|(key, data)| (key, (100, data)))
+            .collect();
+        let mut jupiter_invariant = builder.build().unwrap();
+
+        let rejected = jupiter_invariant.update_with_slots(&accounts).unwrap();
+        assert!(rejected.is_empty());
+        assert_eq!(jupiter_invariant.slot_range(), Some((100, 100)));
+
+        // newer pool
+        {
+            let moved = builder
+                .clone()
+                .current_tick_index(10)
+                .accounts_map()
+                .unwrap();
+            let pool = SlotAccountsMap::from([(
+                builder.market_key,
+                (110, moved[&builder.market_key].clone()),
+            )]);
+            let rejected = jupiter_invariant.update_with_slots(&pool).unwrap();
+            assert!(rejected.is_empty());
+            assert_eq!({ jupiter_invariant.pool.current_tick_index }, 10);
+            assert_eq!(jupiter_invariant.slot_range(), Some((100, 110)));
+        }
+        // older pool delivered out of order
+        {
+            let pool = SlotAccountsMap::from([(
+                builder.market_key,
+                accounts[&builder.market_key].clone(),
+            )]);
+            let rejected = jupiter_invariant.update_with_slots(&pool).unwrap();
+            assert_eq!(rejected, vec![builder.market_key]);
+            assert_eq!({ jupiter_invariant.pool.current_tick_index }, 10);
+            assert_eq!(jupiter_invariant.slot(&builder.market_key), Some(110));
+        }
+    }
+
+    #[test]
+    fn test_slot_consistency() {
+        let builder = PoolBuilder::new(10, FixedPoint::new(0)).position(-100, 100, 1000);
+        let mut jupiter_invariant = builder.build().unwrap();
+        let accounts = builder.accounts_map().unwrap();
+        let pool_key = builder.market_key;
+        let tickmap_key = builder.tickmap_key;
+        let quote_params = QuoteParams {
+            in_amount: 10,
+            input_mint: builder.token_x,
+            output_mint: builder.token_y,
+        };
+
+        // ticks written by `Amm::update` have no slot
+        assert!(matches!(
+            jupiter_invariant.check_slot_consistency(50),
+            Err(InvariantError::MissingSlot(_))
+        ));
+
+        let accounts_with_slots: SlotAccountsMap = accounts
+            .iter()
+            .map(|(key, data)| {
+                let slot = match *key {
+                    key if key == pool_key => 150,
+                    key if key == tickmap_key => 100,
+                    _ => 120,
+                };
+                (*key, (slot, data.clone()))
+            })
+            .collect();
+        let _ = jupiter_invariant
+            .update_with_slots(&accounts_with_slots)
+            .unwrap();
+        assert!(jupiter_invariant.check_slot_consistency(50).is_ok());
+        assert_eq!(
+            jupiter_invariant.check_slot_consistency(10),
+            Err(InvariantError::InconsistentSlots {
+                min_slot: 100,
+                max_slot: 150,
+                max_slot_span: 10
+            })
+        );
+
+        jupiter_invariant.max_slot_span = Some(10);
+        assert!(jupiter_invariant.quote(&quote_params).is_err());
+        jupiter_invariant.max_slot_span = Some(50);
+        assert!(jupiter_invariant.quote(&quote_params).is_ok());
+
+        // a plain update mixed with slot-tagged ones
+        jupiter_invariant
+            .update(&HashMap::from([(pool_key, accounts[&pool_key].clone())]))
+            .unwrap();
+        assert_eq!(
+            jupiter_invariant.check_slot_consistency(50),
+            Err(InvariantError::MissingSlot(pool_key))
+        );
+        assert!(jupiter_invariant.quote(&quote_params).is_err());
+    }
+}
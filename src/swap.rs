@@ -1,18 +1,28 @@
 use std::cell::RefCell;
 
+use anchor_lang::prelude::Pubkey;
 use invariant_types::{
-    decimals::{CheckedOps, Decimal, Price, TokenAmount},
+    decimals::{CheckedOps, Decimal, Liquidity, Price, TokenAmount},
     log::get_tick_at_sqrt_price,
     math::{
         compute_swap_step, cross_tick, get_closer_limit, get_max_sqrt_price, get_max_tick,
         get_min_sqrt_price, get_min_tick, is_enough_amount_to_push_price,
     },
-    structs::TICK_CROSSES_PER_IX,
+    structs::{Pool, TICK_CROSSES_PER_IX},
     MAX_VIRTUAL_CROSS,
 };
-use jupiter_core::amm::QuoteParams;
+use jupiter_core::amm::{Quote, QuoteParams};
 
-use crate::JupiterInvariant;
+use crate::{error::InvariantError, JupiterInvariant};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SwapMode {
+    /// `in_amount` of the quote is the exact amount of the input token sent to the pool
+    #[default]
+    ExactIn,
+    /// `in_amount` of the quote is the exact amount of the output token received from the pool
+    ExactOut,
+}
 
 pub struct InvariantSimulationParams {
     pub in_amount: u64,
@@ -21,6 +31,21 @@ pub struct InvariantSimulationParams {
     pub sqrt_price_limit: Price,
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct SwapStepTrace {
+    pub starting_sqrt_price: Price,
+    pub ending_sqrt_price: Price,
+    pub liquidity: Liquidity,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fee_amount: u64,
+    // tick crossed at the end of the step, set only once the crossing happened, virtual ticks are
+    // not initialized
+    pub crossed_tick: Option<i32>,
+    pub initialized: bool,
+    pub virtual_cross_counter: u16,
+}
+
 #[derive(Clone, Default)]
 pub struct InvariantSwapResult {
     pub in_amount: u64,
@@ -32,25 +57,59 @@ pub struct InvariantSwapResult {
     pub virtual_cross_counter: u16,
     pub global_insufficient_liquidity: bool,
     pub ticks_accounts_outdated: bool,
+    // the swap stopped at the price limit before consuming the whole amount
+    pub price_limit_reached: bool,
+}
+
+// swap amounts expressed in UI units, the fee is paid in the input token
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UiSwapAmounts {
+    pub in_amount: rust_decimal::Decimal,
+    pub out_amount: rust_decimal::Decimal,
+    pub fee_amount: rust_decimal::Decimal,
+}
+
+impl std::fmt::Display for UiSwapAmounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "in: {}, out: {}, fee: {}",
+            self.in_amount, self.out_amount, self.fee_amount
+        )
+    }
 }
 
 impl InvariantSwapResult {
+    pub fn ui_amounts(
+        &self,
+        input_decimals: u8,
+        output_decimals: u8,
+    ) -> Result<UiSwapAmounts, InvariantError> {
+        Ok(UiSwapAmounts {
+            in_amount: JupiterInvariant::amount_to_ui(self.in_amount, input_decimals)?,
+            out_amount: JupiterInvariant::amount_to_ui(self.out_amount, output_decimals)?,
+            fee_amount: JupiterInvariant::amount_to_ui(self.fee_amount, input_decimals)?,
+        })
+    }
+
     pub fn is_not_enough_liquidity(&self) -> bool {
         // since "is_referral" is not specified in the quote parameters, we pessimistically assume that the referral is always used
-        self.ticks_accounts_outdated || self.is_not_enough_liquidity_referral(true)
+        self.is_not_enough_liquidity_referral(true)
     }
 
     pub fn break_swap_loop_early(
         ticks_crossed: u16,
         virtual_ticks_crossed: u16,
-    ) -> Result<bool, String> {
+    ) -> Result<bool, InvariantError> {
         Ok(ticks_crossed
             .checked_add(virtual_ticks_crossed)
-            .ok_or_else(|| "virtual ticks crossed + ticks crossed overflow")?
+            .ok_or(InvariantError::MathOverflow(
+                "virtual ticks crossed + ticks crossed",
+            ))?
             > MAX_VIRTUAL_CROSS + TICK_CROSSES_PER_IX as u16)
     }
 
-    fn is_exceeded_cu_referral(&self, is_referral: bool) -> bool {
+    pub fn is_exceeded_cu_referral(&self, is_referral: bool) -> bool {
         let crossed_amount = self.crossed_ticks.len();
         let mut max_cross = TICK_CROSSES_PER_IX;
         if is_referral {
@@ -63,16 +122,34 @@ impl InvariantSwapResult {
         is_exceeded_by_account_size || is_exceeded_by_compute_units
     }
 
-    fn is_not_enough_liquidity_referral(&self, is_referral: bool) -> bool {
-        self.is_exceeded_cu_referral(is_referral) || self.global_insufficient_liquidity
+    pub fn is_not_enough_liquidity_referral(&self, is_referral: bool) -> bool {
+        self.ticks_accounts_outdated
+            || self.is_exceeded_cu_referral(is_referral)
+            || self.global_insufficient_liquidity
     }
 }
 
 impl JupiterInvariant {
+    // (input mint, output mint) of a swap in the given direction
+    pub fn swap_mints(&self, x_to_y: bool) -> (Pubkey, Pubkey) {
+        match x_to_y {
+            true => (self.pool.token_x, self.pool.token_y),
+            false => (self.pool.token_y, self.pool.token_x),
+        }
+    }
+
     pub fn quote_to_invariant_params(
         &self,
         quote_params: &QuoteParams,
-    ) -> anyhow::Result<InvariantSimulationParams> {
+    ) -> Result<InvariantSimulationParams, InvariantError> {
+        self.quote_to_invariant_params_with_mode(quote_params, SwapMode::ExactIn)
+    }
+
+    pub fn quote_to_invariant_params_with_mode(
+        &self,
+        quote_params: &QuoteParams,
+        swap_mode: SwapMode,
+    ) -> Result<InvariantSimulationParams, InvariantError> {
         let QuoteParams {
             in_amount,
             input_mint,
@@ -82,32 +159,372 @@ impl JupiterInvariant {
         let x_to_y = input_mint.eq(&self.pool.token_x);
         let sqrt_price_limit: Price = if x_to_y {
             get_min_sqrt_price(self.pool.tick_spacing)
-                .map_err(|_| anyhow::anyhow!("failed to calculate min price"))?
+                .map_err(|_| InvariantError::PriceOutOfRange)?
         } else {
             get_max_sqrt_price(self.pool.tick_spacing)
-                .map_err(|_| anyhow::anyhow!("failed to calculate max price"))?
+                .map_err(|_| InvariantError::PriceOutOfRange)?
         };
 
-        let (expected_input_mint, expected_output_mint) = if x_to_y {
-            (self.pool.token_x, self.pool.token_y)
-        } else {
-            (self.pool.token_y, self.pool.token_x)
-        };
+        let (expected_input_mint, expected_output_mint) = self.swap_mints(x_to_y);
         if !(input_mint.eq(&expected_input_mint) && output_mint.eq(&expected_output_mint)) {
-            return Err(anyhow::anyhow!("Invalid source or destination mint"));
+            return Err(InvariantError::InvalidMintPair);
         }
         Ok(InvariantSimulationParams {
             x_to_y,
             in_amount,
-            by_amount_in: true,
+            by_amount_in: swap_mode == SwapMode::ExactIn,
             sqrt_price_limit,
         })
     }
 
+    pub fn quote_to_invariant_params_with_sqrt_price_limit(
+        &self,
+        quote_params: &QuoteParams,
+        sqrt_price_limit: Price,
+    ) -> Result<InvariantSimulationParams, InvariantError> {
+        let mut invariant_simulation_params = self.quote_to_invariant_params(quote_params)?;
+
+        let is_valid_limit = if invariant_simulation_params.x_to_y {
+            let min_sqrt_price = get_min_sqrt_price(self.pool.tick_spacing)
+                .map_err(|_| InvariantError::PriceOutOfRange)?;
+            sqrt_price_limit < self.pool.sqrt_price && sqrt_price_limit >= min_sqrt_price
+        } else {
+            let max_sqrt_price = get_max_sqrt_price(self.pool.tick_spacing)
+                .map_err(|_| InvariantError::PriceOutOfRange)?;
+            sqrt_price_limit > self.pool.sqrt_price && sqrt_price_limit <= max_sqrt_price
+        };
+        if !is_valid_limit {
+            return Err(InvariantError::InvalidPriceLimit);
+        }
+
+        invariant_simulation_params.sqrt_price_limit = sqrt_price_limit;
+        Ok(invariant_simulation_params)
+    }
+
+    // the swap is partially filled when the price limit is reached, `in_amount` of the result is the amount consumed
+    pub fn quote_with_sqrt_price_limit(
+        &self,
+        quote_params: &QuoteParams,
+        sqrt_price_limit: Price,
+    ) -> Result<InvariantSwapResult, InvariantError> {
+        let invariant_simulation_params =
+            self.quote_to_invariant_params_with_sqrt_price_limit(quote_params, sqrt_price_limit)?;
+
+        self.simulate_invariant_swap(&invariant_simulation_params)
+    }
+
+    // price limit is the price of token x denominated in token y
+    pub fn quote_with_price_limit(
+        &self,
+        quote_params: &QuoteParams,
+        price_limit: rust_decimal::Decimal,
+        decimals_x: u8,
+        decimals_y: u8,
+    ) -> Result<InvariantSwapResult, InvariantError> {
+        let sqrt_price_limit = Self::price_to_sqrt_price(price_limit, decimals_x, decimals_y)?;
+
+        self.quote_with_sqrt_price_limit(quote_params, sqrt_price_limit)
+    }
+
+    // max price impact is a fraction, e.g. 0.01 stands for 1%
+    pub fn quote_with_max_price_impact(
+        &self,
+        quote_params: &QuoteParams,
+        max_price_impact: rust_decimal::Decimal,
+    ) -> Result<InvariantSwapResult, InvariantError> {
+        let x_to_y = quote_params.input_mint.eq(&self.pool.token_x);
+        let sqrt_price_limit = self.price_impact_to_sqrt_price_limit(max_price_impact, x_to_y)?;
+
+        self.quote_with_sqrt_price_limit(quote_params, sqrt_price_limit)
+    }
+
+    pub fn quote_with_mode(
+        &self,
+        quote_params: &QuoteParams,
+        swap_mode: SwapMode,
+    ) -> Result<Quote, InvariantError> {
+        self.quote_with_options(quote_params, swap_mode, self.is_referral)
+    }
+
+    // without the referral fee account one more tick account fits in the swap instruction
+    pub fn quote_with_referral(
+        &self,
+        quote_params: &QuoteParams,
+        is_referral: bool,
+    ) -> Result<Quote, InvariantError> {
+        self.quote_with_options(quote_params, self.swap_mode, is_referral)
+    }
+
+    fn quote_with_options(
+        &self,
+        quote_params: &QuoteParams,
+        swap_mode: SwapMode,
+        is_referral: bool,
+    ) -> Result<Quote, InvariantError> {
+        if let Some(max_slot_span) = self.max_slot_span {
+            self.check_slot_consistency(max_slot_span)?;
+        }
+        let accounts_outdated = self.ticks_accounts_outdated()?;
+        if accounts_outdated {
+            return Ok(Quote {
+                not_enough_liquidity: true,
+                ..Quote::default()
+            });
+        }
+
+        let invariant_simulation_params =
+            self.quote_to_invariant_params_with_mode(quote_params, swap_mode)?;
+        let simulation_result = self.simulate_invariant_swap(&invariant_simulation_params);
+
+        Ok(self.simulation_result_to_quote(simulation_result, is_referral))
+    }
+
+    // quotes a ladder of amounts walking the liquidity once, the quotes are sorted by the amount
+    pub fn quote_many(
+        &self,
+        amounts: &[u64],
+        x_to_y: bool,
+    ) -> Result<Vec<(u64, Quote)>, InvariantError> {
+        self.quote_many_with_mode(amounts, x_to_y, self.swap_mode)
+    }
+
+    pub fn quote_many_with_mode(
+        &self,
+        amounts: &[u64],
+        x_to_y: bool,
+        swap_mode: SwapMode,
+    ) -> Result<Vec<(u64, Quote)>, InvariantError> {
+        let mut amounts = amounts.to_vec();
+        amounts.sort_unstable();
+        if let Some(max_slot_span) = self.max_slot_span {
+            self.check_slot_consistency(max_slot_span)?;
+        }
+        if self.ticks_accounts_outdated()? {
+            return Ok(amounts
+                .into_iter()
+                .map(|amount| {
+                    let quote = Quote {
+                        not_enough_liquidity: true,
+                        ..Quote::default()
+                    };
+                    (amount, quote)
+                })
+                .collect());
+        }
+
+        let (input_mint, output_mint) = self.swap_mints(x_to_y);
+        let invariant_simulation_params = self.quote_to_invariant_params_with_mode(
+            &QuoteParams {
+                in_amount: 0,
+                input_mint,
+                output_mint,
+            },
+            swap_mode,
+        )?;
+        // every amount resumes from the last state shared by all larger amounts
+        let mut checkpoint = SwapSimulation::new(self, &invariant_simulation_params, false);
+
+        amounts
+            .into_iter()
+            .map(|amount| {
+                let mut simulation = checkpoint.clone();
+                simulation.remaining_amount = TokenAmount::new(amount)
+                    .checked_sub(checkpoint.consumed_amount())
+                    .map_err(|_| InvariantError::MathOverflow("remaining amount sub"))?;
+                let simulation_result = loop {
+                    match simulation.step() {
+                        Ok(SwapStep::ContinueResumable) => checkpoint = simulation.clone(),
+                        Ok(SwapStep::Continue) => {}
+                        Ok(SwapStep::Finished) => break Ok(simulation.result()),
+                        Err(err) => break Err(err),
+                    }
+                };
+                Ok((
+                    amount,
+                    self.simulation_result_to_quote(simulation_result, self.is_referral),
+                ))
+            })
+            .collect()
+    }
+
+    fn simulation_result_to_quote(
+        &self,
+        simulation_result: Result<InvariantSwapResult, InvariantError>,
+        is_referral: bool,
+    ) -> Quote {
+        match simulation_result {
+            Ok(result) => {
+                let not_enough_liquidity = result.is_not_enough_liquidity_referral(is_referral);
+                let InvariantSwapResult {
+                    in_amount,
+                    out_amount,
+                    fee_amount,
+                    starting_sqrt_price,
+                    ending_sqrt_price,
+                    ..
+                } = result;
+                let price_impact_pct =
+                    Self::calculate_price_impact(starting_sqrt_price, ending_sqrt_price)
+                        .unwrap_or_else(|_| rust_decimal::Decimal::default());
+
+                Quote {
+                    in_amount,
+                    out_amount,
+                    fee_amount,
+                    not_enough_liquidity,
+                    price_impact_pct,
+                    ..Quote::default()
+                }
+            }
+            Err(err) => {
+                log::warn!("{}: swap simulation failed: {}", self.market_key, err);
+                Quote {
+                    not_enough_liquidity: true,
+                    ..Quote::default()
+                }
+            }
+        }
+    }
+
+    // `in_amount` of the quote params is treated as the exact amount of the output token
+    pub fn quote_exact_out(&self, quote_params: &QuoteParams) -> Result<Quote, InvariantError> {
+        self.quote_with_mode(quote_params, SwapMode::ExactOut)
+    }
+
     pub fn simulate_invariant_swap(
         &self,
         invariant_simulation_params: &InvariantSimulationParams,
-    ) -> Result<InvariantSwapResult, String> {
+    ) -> Result<InvariantSwapResult, InvariantError> {
+        let (invariant_swap_result, _) = self.simulate(invariant_simulation_params, false)?;
+        Ok(invariant_swap_result)
+    }
+
+    // same as `simulate_invariant_swap`, but also returns every step of the swap loop
+    pub fn simulate_invariant_swap_with_trace(
+        &self,
+        invariant_simulation_params: &InvariantSimulationParams,
+    ) -> Result<(InvariantSwapResult, Vec<SwapStepTrace>), InvariantError> {
+        self.simulate(invariant_simulation_params, true)
+    }
+
+    fn simulate(
+        &self,
+        invariant_simulation_params: &InvariantSimulationParams,
+        with_trace: bool,
+    ) -> Result<(InvariantSwapResult, Vec<SwapStepTrace>), InvariantError> {
+        let mut simulation = SwapSimulation::new(self, invariant_simulation_params, with_trace);
+        while simulation.step()? != SwapStep::Finished {}
+
+        Ok((simulation.result(), simulation.trace))
+    }
+
+    // largest input amount (and its result) that still fits in a single swap instruction
+    pub fn find_max_in_amount(
+        &self,
+        x_to_y: bool,
+    ) -> Result<Option<InvariantSwapResult>, InvariantError> {
+        if self.ticks_accounts_outdated()? {
+            return Err(InvariantError::TicksAccountsOutdated);
+        }
+        let (input_mint, output_mint) = self.swap_mints(x_to_y);
+        let mut invariant_simulation_params = self.quote_to_invariant_params(&QuoteParams {
+            in_amount: 1,
+            input_mint,
+            output_mint,
+        })?;
+        let mut simulate_fitting = |in_amount: u64| {
+            invariant_simulation_params.in_amount = in_amount;
+            self.simulate_invariant_swap(&invariant_simulation_params)
+                .map(|result| {
+                    (!result.is_not_enough_liquidity_referral(self.is_referral)).then_some(result)
+                })
+        };
+
+        let mut best = match simulate_fitting(1)? {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+        let (mut low, mut high) = (1u64, None);
+        while low < u64::MAX {
+            let amount = low.saturating_mul(2);
+            match simulate_fitting(amount)? {
+                Some(result) => {
+                    (low, best) = (amount, result);
+                }
+                None => {
+                    high = Some(amount);
+                    break;
+                }
+            }
+        }
+        if let Some(mut high) = high {
+            while high - low > 1 {
+                let amount = low + (high - low) / 2;
+                match simulate_fitting(amount)? {
+                    Some(result) => {
+                        (low, best) = (amount, result);
+                    }
+                    None => high = amount,
+                }
+            }
+        }
+
+        Ok(Some(best))
+    }
+
+    fn is_global_sqrt_price_limit(
+        &self,
+        sqrt_price_limit: Price,
+        x_to_y: bool,
+    ) -> Result<bool, InvariantError> {
+        let global_sqrt_price_limit = match x_to_y {
+            true => get_min_sqrt_price(self.pool.tick_spacing),
+            false => get_max_sqrt_price(self.pool.tick_spacing),
+        }
+        .map_err(|_| InvariantError::PriceOutOfRange)?;
+        Ok(global_sqrt_price_limit == sqrt_price_limit)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SwapStep {
+    Continue,
+    // the state after the step is the same for any larger amount, so it can be resumed with more
+    ContinueResumable,
+    Finished,
+}
+
+// state of the swap loop, advanced one step at a time
+#[derive(Clone)]
+struct SwapSimulation<'a> {
+    jupiter_invariant: &'a JupiterInvariant,
+    x_to_y: bool,
+    by_amount_in: bool,
+    sqrt_price_limit: Price,
+    // the state of the pool moved by the swap, the rest is read from the cached pool
+    sqrt_price: Price,
+    liquidity: Liquidity,
+    current_tick_index: i32,
+    starting_sqrt_price: Price,
+    remaining_amount: TokenAmount,
+    total_amount_in: TokenAmount,
+    total_amount_out: TokenAmount,
+    total_fee_amount: TokenAmount,
+    crossed_ticks: Vec<i32>,
+    virtual_cross_counter: u16,
+    global_insufficient_liquidity: bool,
+    ticks_accounts_outdated: bool,
+    price_limit_reached: bool,
+    with_trace: bool,
+    trace: Vec<SwapStepTrace>,
+}
+
+impl<'a> SwapSimulation<'a> {
+    fn new(
+        jupiter_invariant: &'a JupiterInvariant,
+        invariant_simulation_params: &InvariantSimulationParams,
+        with_trace: bool,
+    ) -> Self {
         let InvariantSimulationParams {
             in_amount,
             x_to_y,
@@ -115,173 +532,753 @@ impl JupiterInvariant {
             by_amount_in,
         } = *invariant_simulation_params;
 
-        let (pool, ticks, tickmap, starting_sqrt_price) = (
-            &mut self.pool.clone(),
-            &self.ticks.clone(),
-            &self.tickmap.clone(),
-            self.pool.sqrt_price,
-        );
-        let (mut remaining_amount, mut total_amount_in, mut total_amount_out, mut total_fee_amount) = (
-            TokenAmount::new(in_amount),
-            TokenAmount::new(0),
-            TokenAmount::new(0),
-            TokenAmount::new(0),
-        );
-        let (
-            mut crossed_ticks,
-            mut virtual_cross_counter,
-            mut global_insufficient_liquidity,
-            mut ticks_accounts_outdated,
-        ) = (Vec::new(), 0u16, false, false);
-
-        while !remaining_amount.is_zero() {
-            let (swap_limit, limiting_tick) = match get_closer_limit(
-                sqrt_price_limit,
-                x_to_y,
-                pool.current_tick_index,
-                pool.tick_spacing,
-                tickmap,
-            ) {
-                Ok((swap_limit, limiting_tick)) => (swap_limit, limiting_tick),
-                Err(_) => {
-                    global_insufficient_liquidity = true;
-                    break;
-                }
-            };
+        Self {
+            jupiter_invariant,
+            x_to_y,
+            by_amount_in,
+            sqrt_price_limit,
+            sqrt_price: jupiter_invariant.pool.sqrt_price,
+            liquidity: jupiter_invariant.pool.liquidity,
+            current_tick_index: jupiter_invariant.pool.current_tick_index,
+            starting_sqrt_price: jupiter_invariant.pool.sqrt_price,
+            remaining_amount: TokenAmount::new(in_amount),
+            total_amount_in: TokenAmount::new(0),
+            total_amount_out: TokenAmount::new(0),
+            total_fee_amount: TokenAmount::new(0),
+            crossed_ticks: Vec::new(),
+            virtual_cross_counter: 0,
+            global_insufficient_liquidity: false,
+            ticks_accounts_outdated: false,
+            price_limit_reached: false,
+            with_trace,
+            trace: Vec::new(),
+        }
+    }
 
-            let result = compute_swap_step(
-                pool.sqrt_price,
-                swap_limit,
-                pool.liquidity,
-                remaining_amount,
-                by_amount_in,
+    // amount of the token the swap is specified in, consumed so far
+    fn consumed_amount(&self) -> TokenAmount {
+        match self.by_amount_in {
+            true => self.total_amount_in,
+            false => self.total_amount_out,
+        }
+    }
+
+    fn result(&self) -> InvariantSwapResult {
+        InvariantSwapResult {
+            in_amount: self.total_amount_in.0,
+            out_amount: self.total_amount_out.0,
+            fee_amount: self.total_fee_amount.0,
+            starting_sqrt_price: self.starting_sqrt_price,
+            ending_sqrt_price: self.sqrt_price,
+            crossed_ticks: self.crossed_ticks.clone(),
+            virtual_cross_counter: self.virtual_cross_counter,
+            global_insufficient_liquidity: self.global_insufficient_liquidity,
+            ticks_accounts_outdated: self.ticks_accounts_outdated,
+            price_limit_reached: self.price_limit_reached,
+        }
+    }
+
+    fn step(&mut self) -> Result<SwapStep, InvariantError> {
+        let (x_to_y, by_amount_in, sqrt_price_limit) =
+            (self.x_to_y, self.by_amount_in, self.sqrt_price_limit);
+        let pool: &'a Pool = &self.jupiter_invariant.pool;
+        if self.remaining_amount.is_zero() {
+            return Ok(SwapStep::Finished);
+        }
+
+        let (swap_limit, limiting_tick) = match get_closer_limit(
+            sqrt_price_limit,
+            x_to_y,
+            self.current_tick_index,
+            pool.tick_spacing,
+            &self.jupiter_invariant.tickmap,
+        ) {
+            Ok((swap_limit, limiting_tick)) => (swap_limit, limiting_tick),
+            Err(_) => {
+                self.global_insufficient_liquidity = true;
+                return Ok(SwapStep::Finished);
+            }
+        };
+
+        let result = compute_swap_step(
+            self.sqrt_price,
+            swap_limit,
+            self.liquidity,
+            self.remaining_amount,
+            by_amount_in,
+            pool.fee,
+        )
+        .map_err(|e| {
+            let (formatted, _, _) = e.get();
+            InvariantError::MathError(formatted)
+        })?;
+
+        if self.with_trace {
+            self.trace.push(SwapStepTrace {
+                starting_sqrt_price: self.sqrt_price,
+                ending_sqrt_price: result.next_price_sqrt,
+                liquidity: self.liquidity,
+                amount_in: result.amount_in.0,
+                amount_out: result.amount_out.0,
+                fee_amount: result.fee_amount.0,
+                virtual_cross_counter: self.virtual_cross_counter,
+                ..Default::default()
+            });
+        }
+
+        let step_amount_in = result
+            .amount_in
+            .checked_add(result.fee_amount)
+            .map_err(|_| InvariantError::MathOverflow("amount in + fee amount"))?;
+        self.remaining_amount = match by_amount_in {
+            true => self.remaining_amount.checked_sub(step_amount_in),
+            false => self.remaining_amount.checked_sub(result.amount_out),
+        }
+        .map_err(|_| InvariantError::MathOverflow("remaining amount sub"))?;
+        self.sqrt_price = result.next_price_sqrt;
+        self.total_amount_in = self
+            .total_amount_in
+            .checked_add(step_amount_in)
+            .map_err(|_| InvariantError::MathOverflow("total amount in add"))?;
+        self.total_amount_out = self
+            .total_amount_out
+            .checked_add(result.amount_out)
+            .map_err(|_| InvariantError::MathOverflow("total amount out add"))?;
+        self.total_fee_amount = self
+            .total_fee_amount
+            .checked_add(result.fee_amount)
+            .map_err(|_| InvariantError::MathOverflow("total fee amount add"))?;
+
+        if self.sqrt_price == sqrt_price_limit && !self.remaining_amount.is_zero() {
+            // a limit set by the user partially fills the swap instead of failing it
+            self.price_limit_reached = true;
+            self.global_insufficient_liquidity = self
+                .jupiter_invariant
+                .is_global_sqrt_price_limit(sqrt_price_limit, x_to_y)?;
+            return Ok(SwapStep::Finished);
+        }
+        let reached_tick_limit = match x_to_y {
+            true => {
+                self.current_tick_index
+                    <= get_min_tick(pool.tick_spacing)
+                        .map_err(|err| InvariantError::MathError(err.cause))?
+            }
+            false => {
+                self.current_tick_index
+                    >= get_max_tick(pool.tick_spacing)
+                        .map_err(|err| InvariantError::MathError(err.cause))?
+            }
+        };
+        if reached_tick_limit {
+            self.global_insufficient_liquidity = true;
+            return Ok(SwapStep::Finished);
+        }
+
+        // the step is the same for any larger amount only when the price reached the next
+        // initialized tick and the crossing decision does not depend on the remaining amount
+        let mut resumable = false;
+        // crossing tick
+        if result.next_price_sqrt == swap_limit && limiting_tick.is_some() {
+            let (tick_index, initialized) = limiting_tick.unwrap();
+            let is_enough_amount_to_cross = is_enough_amount_to_push_price(
+                self.remaining_amount,
+                result.next_price_sqrt,
+                self.liquidity,
                 pool.fee,
+                by_amount_in,
+                x_to_y,
             )
             .map_err(|e| {
                 let (formatted, _, _) = e.get();
-                formatted
+                InvariantError::MathError(formatted)
             })?;
+            resumable = !x_to_y || is_enough_amount_to_cross;
 
-            remaining_amount =
-                remaining_amount.checked_sub(result.amount_in.checked_add(result.fee_amount)?)?;
-            pool.sqrt_price = result.next_price_sqrt;
-            total_amount_in = total_amount_in
-                .checked_add(result.amount_in)?
-                .checked_add(result.fee_amount)?;
-            total_amount_out = total_amount_out.checked_add(result.amount_out)?;
-            total_fee_amount = total_fee_amount.checked_add(result.fee_amount)?;
-
-            if { pool.sqrt_price } == sqrt_price_limit && !remaining_amount.is_zero() {
-                global_insufficient_liquidity = true;
-                break;
-            }
-            let reached_tick_limit = match x_to_y {
-                true => {
-                    pool.current_tick_index
-                        <= get_min_tick(pool.tick_spacing).map_err(|err| err.cause)?
-                }
-                false => {
-                    pool.current_tick_index
-                        >= get_max_tick(pool.tick_spacing).map_err(|err| err.cause)?
-                }
-            };
-            if reached_tick_limit {
-                global_insufficient_liquidity = true;
-                break;
-            }
+            if initialized {
+                let tick_address = self.jupiter_invariant.tick_index_to_address(tick_index);
+                // a tick is crossed at most once per swap, so the copy is never read again
+                let tick = match self.jupiter_invariant.ticks.get(&tick_address) {
+                    Some(tick) => RefCell::new(*tick),
+                    None => {
+                        self.ticks_accounts_outdated = true;
+                        return Ok(SwapStep::Finished);
+                    }
+                };
+                // `cross_tick` takes the tick through a `RefMut` like the on-chain account loader
+                let mut tick = tick.borrow_mut();
 
-            // crossing tick
-            if result.next_price_sqrt == swap_limit && limiting_tick.is_some() {
-                let (tick_index, initialized) = limiting_tick.unwrap();
-                let is_enough_amount_to_cross = is_enough_amount_to_push_price(
-                    remaining_amount,
-                    result.next_price_sqrt,
-                    pool.liquidity,
-                    pool.fee,
-                    by_amount_in,
-                    x_to_y,
-                )
-                .map_err(|e| {
-                    let (formatted, _, _) = e.get();
-                    formatted
-                })?;
-
-                if initialized {
-                    let tick_address = self.tick_index_to_address(tick_index);
-                    let tick = match ticks.get(&tick_address) {
-                        Some(tick) => RefCell::new(*tick),
-                        None => {
-                            ticks_accounts_outdated = true;
-                            break;
-                        }
+                // crossing tick
+                if !x_to_y || is_enough_amount_to_cross {
+                    // `cross_tick` updates a copy of the pool, only its liquidity is carried over
+                    let mut crossed_pool = Pool {
+                        sqrt_price: self.sqrt_price,
+                        liquidity: self.liquidity,
+                        current_tick_index: self.current_tick_index,
+                        ..*pool
                     };
-                    let mut tick = tick.borrow_mut();
-
-                    // crossing tick
-                    if !x_to_y || is_enough_amount_to_cross {
-                        let cross_tick_result = cross_tick(&mut tick, pool);
-                        if cross_tick_result.is_err() {
-                            global_insufficient_liquidity = true;
-                            break;
-                        }
-                        crossed_ticks.push(tick.index);
-                    } else if !remaining_amount.is_zero() {
-                        total_amount_in = total_amount_in
-                            .checked_add(remaining_amount)
-                            .map_err(|_| "add overflow")?;
-                        remaining_amount = TokenAmount(0);
+                    let cross_tick_result = cross_tick(&mut tick, &mut crossed_pool);
+                    if cross_tick_result.is_err() {
+                        self.global_insufficient_liquidity = true;
+                        return Ok(SwapStep::Finished);
                     }
-                } else {
-                    virtual_cross_counter =
-                        virtual_cross_counter.checked_add(1).ok_or("add overflow")?;
-                    if InvariantSwapResult::break_swap_loop_early(
-                        crossed_ticks.len() as u16,
-                        virtual_cross_counter,
-                    )? {
-                        global_insufficient_liquidity = true;
-                        break;
+                    self.liquidity = crossed_pool.liquidity;
+                    self.crossed_ticks.push(tick.index);
+                    if let Some(step) = self.trace.last_mut() {
+                        step.crossed_tick = Some(tick_index);
+                        step.initialized = true;
                     }
+                } else if !self.remaining_amount.is_zero() {
+                    // in the exact output mode the remaining amount is denominated in the output token
+                    if by_amount_in {
+                        self.total_amount_in = self
+                            .total_amount_in
+                            .checked_add(self.remaining_amount)
+                            .map_err(|_| InvariantError::MathOverflow("total amount in add"))?;
+                    }
+                    self.remaining_amount = TokenAmount(0);
                 }
-
-                pool.current_tick_index = if x_to_y && is_enough_amount_to_cross {
-                    tick_index
-                        .checked_sub(pool.tick_spacing as i32)
-                        .ok_or("sub overflow")?
-                } else {
-                    tick_index
-                };
             } else {
-                if pool
-                    .current_tick_index
-                    .checked_rem(pool.tick_spacing.into())
-                    .unwrap()
-                    != 0
-                {
-                    return Err("Internal Invariant Error: Invalid tick".to_string());
+                self.virtual_cross_counter = self
+                    .virtual_cross_counter
+                    .checked_add(1)
+                    .ok_or(InvariantError::MathOverflow("virtual cross counter add"))?;
+                if let Some(step) = self.trace.last_mut() {
+                    step.crossed_tick = Some(tick_index);
+                    step.virtual_cross_counter = self.virtual_cross_counter;
                 }
-                pool.current_tick_index =
-                    get_tick_at_sqrt_price(result.next_price_sqrt, pool.tick_spacing);
-                virtual_cross_counter =
-                    virtual_cross_counter.checked_add(1).ok_or("add overflow")?;
                 if InvariantSwapResult::break_swap_loop_early(
-                    crossed_ticks.len() as u16,
-                    virtual_cross_counter,
+                    self.crossed_ticks.len() as u16,
+                    self.virtual_cross_counter,
                 )? {
-                    global_insufficient_liquidity = true;
-                    break;
+                    self.global_insufficient_liquidity = true;
+                    return Ok(SwapStep::Finished);
                 }
             }
+
+            self.current_tick_index = if x_to_y && is_enough_amount_to_cross {
+                tick_index
+                    .checked_sub(pool.tick_spacing as i32)
+                    .ok_or(InvariantError::MathOverflow("tick index sub"))?
+            } else {
+                tick_index
+            };
+        } else {
+            if self
+                .current_tick_index
+                .checked_rem(pool.tick_spacing.into())
+                .ok_or(InvariantError::MathOverflow("tick index rem"))?
+                != 0
+            {
+                return Err(InvariantError::TickSpacingViolation {
+                    tick_index: self.current_tick_index,
+                    tick_spacing: pool.tick_spacing,
+                });
+            }
+            self.current_tick_index =
+                get_tick_at_sqrt_price(result.next_price_sqrt, pool.tick_spacing);
+            self.virtual_cross_counter = self
+                .virtual_cross_counter
+                .checked_add(1)
+                .ok_or(InvariantError::MathOverflow("virtual cross counter add"))?;
+            if let Some(step) = self.trace.last_mut() {
+                step.virtual_cross_counter = self.virtual_cross_counter;
+            }
+            if InvariantSwapResult::break_swap_loop_early(
+                self.crossed_ticks.len() as u16,
+                self.virtual_cross_counter,
+            )? {
+                self.global_insufficient_liquidity = true;
+                return Ok(SwapStep::Finished);
+            }
         }
-        Ok(InvariantSwapResult {
-            in_amount: total_amount_in.0,
-            out_amount: total_amount_out.0,
-            fee_amount: total_fee_amount.0,
-            starting_sqrt_price,
-            ending_sqrt_price: pool.sqrt_price,
-            crossed_ticks,
-            virtual_cross_counter,
-            global_insufficient_liquidity,
-            ticks_accounts_outdated,
+
+        Ok(match (self.remaining_amount.is_zero(), resumable) {
+            (true, _) => SwapStep::Finished,
+            (false, true) => SwapStep::ContinueResumable,
+            (false, false) => SwapStep::Continue,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anchor_lang::{prelude::Pubkey, AnchorDeserialize};
+    use invariant_types::{
+        decimals::{Decimal, FixedPoint, Liquidity},
+        math::{calculate_price_sqrt, get_max_sqrt_price, get_min_sqrt_price},
+        structs::TICK_CROSSES_PER_IX,
+        MAX_VIRTUAL_CROSS,
+    };
+    use jupiter_core::amm::{Amm, QuoteParams, SwapParams};
+
+    use crate::{
+        accounts::InvariantSwapArgs,
+        error::InvariantError,
+        swap::{InvariantSimulationParams, InvariantSwapResult, SwapMode},
+        test_utils::PoolBuilder,
+        JupiterInvariant,
+    };
+
+    // 0.01% fee, two overlapping positions around the current tick
+    fn two_positions_pool(current_tick_index: i32) -> JupiterInvariant {
+        PoolBuilder::new(10, FixedPoint::new(100000000))
+            .current_tick_index(current_tick_index)
+            .position(-100, 100, 1_000_000_000000)
+            .position(-20, 20, 1_000_000_000000)
+            .build()
+            .unwrap()
+    }
+
+    fn simulation_params(
+        jupiter_invariant: &JupiterInvariant,
+        in_amount: u64,
+        x_to_y: bool,
+    ) -> InvariantSimulationParams {
+        let sqrt_price_limit = match x_to_y {
+            true => get_min_sqrt_price(jupiter_invariant.pool.tick_spacing),
+            false => get_max_sqrt_price(jupiter_invariant.pool.tick_spacing),
+        }
+        .unwrap();
+        InvariantSimulationParams {
+            in_amount,
+            x_to_y,
+            by_amount_in: true,
+            sqrt_price_limit,
+        }
+    }
+
+    #[test]
+    fn test_is_not_enough_liquidity_referral() {
+        // all tick accounts used
+        {
+            let result = InvariantSwapResult {
+                crossed_ticks: vec![0; TICK_CROSSES_PER_IX],
+                ..Default::default()
+            };
+            assert!(result.is_not_enough_liquidity_referral(true));
+            assert!(!result.is_not_enough_liquidity_referral(false));
+            assert!(result.is_not_enough_liquidity());
+        }
+        // all tick accounts used and too many virtual crosses
+        {
+            let result = InvariantSwapResult {
+                crossed_ticks: vec![0; TICK_CROSSES_PER_IX],
+                virtual_cross_counter: MAX_VIRTUAL_CROSS + 1,
+                ..Default::default()
+            };
+            assert!(result.is_not_enough_liquidity_referral(false));
+        }
+        // outdated ticks
+        {
+            let result = InvariantSwapResult {
+                ticks_accounts_outdated: true,
+                ..Default::default()
+            };
+            assert!(result.is_not_enough_liquidity_referral(false));
+        }
+    }
+
+    #[test]
+    fn test_swap_without_crossing() {
+        let jupiter_invariant = two_positions_pool(0);
+
+        for x_to_y in [true, false] {
+            let params = simulation_params(&jupiter_invariant, 100, x_to_y);
+            let result = jupiter_invariant.simulate_invariant_swap(&params).unwrap();
+            assert!(result.crossed_ticks.is_empty());
+            assert!(!result.is_not_enough_liquidity());
+            assert!(result.out_amount > 0);
+        }
+    }
+
+    #[test]
+    fn test_swap_crossing_initialized_tick() {
+        let jupiter_invariant = two_positions_pool(0);
+
+        // x to y
+        {
+            let params = simulation_params(&jupiter_invariant, 3000, true);
+            let (result, trace) = jupiter_invariant
+                .simulate_invariant_swap_with_trace(&params)
+                .unwrap();
+            assert_eq!(result.crossed_ticks, vec![-20]);
+            assert!(!result.is_not_enough_liquidity());
+            assert_eq!(
+                trace.first().unwrap().liquidity,
+                Liquidity::new(2_000_000_000000)
+            );
+            assert_eq!(
+                trace.last().unwrap().liquidity,
+                Liquidity::new(1_000_000_000000)
+            );
+        }
+        // y to x
+        {
+            let params = simulation_params(&jupiter_invariant, 3000, false);
+            let (result, trace) = jupiter_invariant
+                .simulate_invariant_swap_with_trace(&params)
+                .unwrap();
+            assert_eq!(result.crossed_ticks, vec![20]);
+            assert!(!result.is_not_enough_liquidity());
+            assert_eq!(
+                trace.last().unwrap().liquidity,
+                Liquidity::new(1_000_000_000000)
+            );
+        }
+    }
+
+    #[test]
+    fn test_swap_from_initialized_tick() {
+        // the price sits exactly on the lower tick of the narrow position
+        let jupiter_invariant = two_positions_pool(-20);
+        assert_eq!(
+            { jupiter_invariant.pool.liquidity },
+            Liquidity::new(2_000_000_000000)
+        );
+
+        // x to y crosses the current tick first
+        {
+            let params = simulation_params(&jupiter_invariant, 3000, true);
+            let result = jupiter_invariant.simulate_invariant_swap(&params).unwrap();
+            assert_eq!(result.crossed_ticks.first(), Some(&-20));
+        }
+        // y to x stays in the range of the current tick
+        {
+            let params = simulation_params(&jupiter_invariant, 100, false);
+            let result = jupiter_invariant.simulate_invariant_swap(&params).unwrap();
+            assert!(result.crossed_ticks.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_swap_exceeding_liquidity() {
+        let jupiter_invariant = two_positions_pool(0);
+
+        for x_to_y in [true, false] {
+            let params = simulation_params(&jupiter_invariant, u64::MAX / 2, x_to_y);
+            let result = jupiter_invariant.simulate_invariant_swap(&params).unwrap();
+            assert!(result.global_insufficient_liquidity);
+            assert!(result.is_not_enough_liquidity());
+            assert_eq!(result.crossed_ticks.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_quote_many() {
+        let mut jupiter_invariant = two_positions_pool(0);
+        let amounts = [
+            500_000, 1, 1000, 1000, 1020, 3000, 4500, 5000, 5600, 6000, 100_000, 3,
+        ];
+
+        for swap_mode in [SwapMode::ExactIn, SwapMode::ExactOut] {
+            jupiter_invariant.swap_mode = swap_mode;
+            for x_to_y in [true, false] {
+                let (input_mint, output_mint) = jupiter_invariant.swap_mints(x_to_y);
+                let quotes = jupiter_invariant.quote_many(&amounts, x_to_y).unwrap();
+
+                assert_eq!(quotes.len(), amounts.len());
+                assert!(quotes.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+                for (amount, quote) in quotes {
+                    let expected = jupiter_invariant
+                        .quote(&QuoteParams {
+                            in_amount: amount,
+                            input_mint,
+                            output_mint,
+                        })
+                        .unwrap();
+                    assert_eq!(quote.in_amount, expected.in_amount, "{}", amount);
+                    assert_eq!(quote.out_amount, expected.out_amount, "{}", amount);
+                    assert_eq!(quote.fee_amount, expected.fee_amount, "{}", amount);
+                    assert_eq!(
+                        quote.not_enough_liquidity, expected.not_enough_liquidity,
+                        "{}",
+                        amount
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_quote_many_exact_out() {
+        // 1% fee, the swaps end inside the inner range, on its edge and beyond it
+        let dense = PoolBuilder::new(10, FixedPoint::new(10000000000))
+            .position(-100, 100, 1_000_000_000000)
+            .position(-20, 20, 1_000_000_000000);
+        // the search for the next tick ends before the only range, so the price stops between ticks
+        let sparse = PoolBuilder::new(1, FixedPoint::new(10000000000)).position(
+            -1000,
+            1000,
+            1_000_000_000000,
+        );
+        let amounts = [
+            1, 10, 100, 500, 1000, 1005, 1010, 1500, 3000, 10_000, 1_000_000,
+        ];
+
+        for builder in [dense, sparse] {
+            let mut jupiter_invariant = builder.build().unwrap();
+            jupiter_invariant.swap_mode = SwapMode::ExactOut;
+            for x_to_y in [true, false] {
+                let (input_mint, output_mint) = jupiter_invariant.swap_mints(x_to_y);
+                let quotes = jupiter_invariant.quote_many(&amounts, x_to_y).unwrap();
+
+                assert_eq!(quotes.len(), amounts.len());
+                for (amount, quote) in quotes {
+                    let expected = jupiter_invariant
+                        .quote(&QuoteParams {
+                            in_amount: amount,
+                            input_mint,
+                            output_mint,
+                        })
+                        .unwrap();
+                    assert_eq!(quote.in_amount, expected.in_amount, "{}", amount);
+                    assert_eq!(quote.out_amount, expected.out_amount, "{}", amount);
+                    assert_eq!(quote.fee_amount, expected.fee_amount, "{}", amount);
+                    assert_eq!(
+                        quote.not_enough_liquidity, expected.not_enough_liquidity,
+                        "{}",
+                        amount
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_quote_exact_out() {
+        // 1% fee, so the input of an exact output swap is noticeably larger than its output
+        let mut jupiter_invariant = PoolBuilder::new(10, FixedPoint::new(10000000000))
+            .position(-100, 100, 1_000_000_000000)
+            .position(-20, 20, 1_000_000_000000)
+            .build()
+            .unwrap();
+        let (token_x, token_y) = (
+            jupiter_invariant.pool.token_x,
+            jupiter_invariant.pool.token_y,
+        );
+        let quote_params = |in_amount: u64| QuoteParams {
+            in_amount,
+            input_mint: token_x,
+            output_mint: token_y,
+        };
+
+        // within the range of the current tick
+        {
+            let quote = jupiter_invariant
+                .quote_exact_out(&quote_params(100))
+                .unwrap();
+            assert!(!quote.not_enough_liquidity);
+            assert_eq!(quote.out_amount, 100);
+            assert!(quote.in_amount > 100 && quote.fee_amount > 0);
+        }
+        // ~1000 of token y moves the price to the tick -20, which an exact input of 1005 does not reach
+        {
+            let quote = jupiter_invariant
+                .quote_exact_out(&quote_params(1005))
+                .unwrap();
+            assert!(!quote.not_enough_liquidity);
+            assert_eq!(quote.out_amount, 1005);
+
+            let mut params = simulation_params(&jupiter_invariant, 1005, true);
+            params.by_amount_in = false;
+            let result = jupiter_invariant.simulate_invariant_swap(&params).unwrap();
+            assert_eq!(result.crossed_ticks, vec![-20]);
+            assert_eq!(result.in_amount, quote.in_amount);
+
+            params.by_amount_in = true;
+            let result = jupiter_invariant.simulate_invariant_swap(&params).unwrap();
+            assert!(result.crossed_ticks.is_empty());
+        }
+        // more than the whole liquidity of the pool
+        {
+            let quote = jupiter_invariant
+                .quote_exact_out(&quote_params(1_000_000))
+                .unwrap();
+            assert!(quote.not_enough_liquidity);
+        }
+
+        // `Amm::quote` and the swap instruction follow the mode of the pool
+        jupiter_invariant.swap_mode = SwapMode::ExactOut;
+        assert_eq!(
+            jupiter_invariant
+                .quote(&quote_params(1005))
+                .unwrap()
+                .out_amount,
+            1005
+        );
+        let swap_params = SwapParams {
+            source_mint: token_x,
+            destination_mint: token_y,
+            user_source_token_account: Pubkey::new_unique(),
+            user_destination_token_account: Pubkey::new_unique(),
+            user_transfer_authority: Pubkey::new_unique(),
+            open_order_address: None,
+            quote_mint_to_referrer: None,
+            in_amount: 1005,
+        };
+        let (instruction, _) = jupiter_invariant.swap_instruction(&swap_params).unwrap();
+        let crossed_tick = jupiter_invariant.tick_index_to_address(-20);
+        assert!(instruction
+            .accounts
+            .iter()
+            .any(|meta| meta.pubkey == crossed_tick));
+        // the swap leg would execute 1005 as the input
+        assert_eq!(
+            jupiter_invariant
+                .get_swap_leg_and_account_metas(&swap_params)
+                .err()
+                .unwrap()
+                .downcast::<InvariantError>()
+                .unwrap(),
+            InvariantError::ExactOutSwapLeg
+        );
+    }
+
+    #[test]
+    fn test_swap_leg_with_sqrt_price_limit() {
+        let jupiter_invariant = two_positions_pool(0);
+        let sqrt_price_limit = calculate_price_sqrt(-50);
+        let swap_params = |in_amount: u64| SwapParams {
+            source_mint: jupiter_invariant.pool.token_x,
+            destination_mint: jupiter_invariant.pool.token_y,
+            user_source_token_account: Pubkey::new_unique(),
+            user_destination_token_account: Pubkey::new_unique(),
+            user_transfer_authority: Pubkey::new_unique(),
+            open_order_address: None,
+            quote_mint_to_referrer: None,
+            in_amount,
+        };
+
+        // the limit is not reached, the leg executes the quoted swap
+        {
+            let swap_params = swap_params(1500);
+            let (swap_leg_and_account_metas, result) = jupiter_invariant
+                .get_swap_leg_and_account_metas_with_sqrt_price_limit(
+                    &swap_params,
+                    sqrt_price_limit,
+                )
+                .unwrap();
+            assert!(!result.price_limit_reached);
+            assert_eq!(result.in_amount, 1500);
+            assert_eq!(result.crossed_ticks, vec![-20]);
+            assert_eq!(
+                swap_leg_and_account_metas.account_metas,
+                jupiter_invariant
+                    .get_swap_leg_and_account_metas(&swap_params)
+                    .unwrap()
+                    .account_metas
+            );
+        }
+        // the limit is reached, only the instruction carries it
+        {
+            let swap_params = swap_params(100_000);
+            assert!(matches!(
+                jupiter_invariant.get_swap_leg_and_account_metas_with_sqrt_price_limit(
+                    &swap_params,
+                    sqrt_price_limit,
+                ),
+                Err(InvariantError::PriceLimitReached)
+            ));
+
+            let (instruction, result) = jupiter_invariant
+                .swap_instruction_with_sqrt_price_limit(&swap_params, sqrt_price_limit)
+                .unwrap();
+            assert!(result.price_limit_reached);
+            assert!(result.in_amount < 100_000);
+            assert_eq!(result.ending_sqrt_price, sqrt_price_limit);
+            let args = InvariantSwapArgs::deserialize(&mut &instruction.data[8..]).unwrap();
+            assert_eq!(args.sqrt_price_limit, sqrt_price_limit.v);
+            assert_eq!(args.amount, 100_000);
+        }
+    }
+
+    #[test]
+    fn test_swap_trace() {
+        // crossed tick
+        {
+            let jupiter_invariant = two_positions_pool(0);
+            let params = simulation_params(&jupiter_invariant, 3000, true);
+            let (_, trace) = jupiter_invariant
+                .simulate_invariant_swap_with_trace(&params)
+                .unwrap();
+            assert_eq!(trace.first().unwrap().crossed_tick, Some(-20));
+            assert!(trace.first().unwrap().initialized);
+            assert_eq!(trace.last().unwrap().crossed_tick, None);
+        }
+        // the account of the reached tick is missing, so the tick is not crossed
+        {
+            let mut jupiter_invariant = two_positions_pool(0);
+            let _ = jupiter_invariant
+                .ticks
+                .remove(&jupiter_invariant.tick_index_to_address(-20));
+            let params = simulation_params(&jupiter_invariant, 3000, true);
+            let (result, trace) = jupiter_invariant
+                .simulate_invariant_swap_with_trace(&params)
+                .unwrap();
+            assert!(result.ticks_accounts_outdated);
+            assert!(result.crossed_ticks.is_empty());
+            assert!(trace.iter().all(|step| step.crossed_tick.is_none()));
+        }
+        // the closest initialized tick is beyond the tickmap search range
+        {
+            let jupiter_invariant = PoolBuilder::new(1, FixedPoint::new(0))
+                .position(-1000, 1000, 1_000_000_000000)
+                .build()
+                .unwrap();
+            let params = simulation_params(&jupiter_invariant, 20000, true);
+            let (result, trace) = jupiter_invariant
+                .simulate_invariant_swap_with_trace(&params)
+                .unwrap();
+            assert!(result.crossed_ticks.is_empty());
+            assert!(result.virtual_cross_counter > 0);
+            assert!(trace.iter().any(|step| step.crossed_tick.is_some()
+                && !step.initialized
+                && step.virtual_cross_counter > 0));
+        }
+    }
+
+    #[test]
+    fn test_find_max_in_amount() {
+        // the whole liquidity is in the range of the current tick
+        {
+            let jupiter_invariant = PoolBuilder::new(10, FixedPoint::new(100000000))
+                .position(-100, 100, 1_000_000_000000)
+                .build()
+                .unwrap();
+            let best = jupiter_invariant.find_max_in_amount(true).unwrap().unwrap();
+            assert!(!best.is_not_enough_liquidity_referral(jupiter_invariant.is_referral));
+            assert!(best.ending_sqrt_price >= calculate_price_sqrt(-100));
+
+            let params = simulation_params(&jupiter_invariant, best.in_amount + 1, true);
+            let result = jupiter_invariant.simulate_invariant_swap(&params).unwrap();
+            assert!(result.global_insufficient_liquidity);
+        }
+        // more initialized ticks below the price than fit in the instruction
+        {
+            let mut builder = PoolBuilder::new(10, FixedPoint::new(100000000));
+            for index in 1..=TICK_CROSSES_PER_IX as i32 + 5 {
+                builder = builder.position(-10 * index, 1000, 1_000_000_000000);
+            }
+            let jupiter_invariant = builder.build().unwrap();
+            let best = jupiter_invariant.find_max_in_amount(true).unwrap().unwrap();
+            assert!(!best.is_not_enough_liquidity_referral(jupiter_invariant.is_referral));
+            assert!(!best.crossed_ticks.is_empty());
+
+            // one more unit exceeds the ticks accounts of the instruction
+            let params = simulation_params(&jupiter_invariant, best.in_amount + 1, true);
+            let result = jupiter_invariant.simulate_invariant_swap(&params).unwrap();
+            assert!(!result.global_insufficient_liquidity);
+            assert!(result.is_not_enough_liquidity_referral(jupiter_invariant.is_referral));
+        }
+        // no liquidity at all
+        {
+            let jupiter_invariant = PoolBuilder::new(10, FixedPoint::new(100000000))
+                .build()
+                .unwrap();
+            assert!(jupiter_invariant
+                .find_max_in_amount(true)
+                .unwrap()
+                .is_none());
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use anchor_lang::{prelude::Pubkey, AnchorSerialize};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use invariant_types::{
+    decimals::{Decimal, FixedPoint, Liquidity, Price},
+    math::calculate_price_sqrt,
+    structs::{Pool, Tick, Tickmap, TICK_LIMIT},
+    ID,
+};
+use jupiter_core::amm::KeyedAccount;
+use serde_json::{json, Value};
+use solana_sdk::{account::Account, bs58};
+
+use crate::{error::InvariantError, utiles::InvariantAccount, JupiterInvariant};
+
+// builds a pool with hand-specified liquidity, encoded exactly as the on-chain accounts
+#[derive(Clone, Debug)]
+pub struct PoolBuilder {
+    pub market_key: Pubkey,
+    pub tickmap_key: Pubkey,
+    pub token_x: Pubkey,
+    pub token_y: Pubkey,
+    pub tick_spacing: u16,
+    pub fee: FixedPoint,
+    pub current_tick_index: i32,
+    // defaults to the sqrt price of the current tick
+    pub sqrt_price: Option<Price>,
+    // tick index -> (liquidity change, liquidity gross) in the `Liquidity` scale
+    pub ticks: BTreeMap<i32, (i128, u128)>,
+}
+
+impl PoolBuilder {
+    pub fn new(tick_spacing: u16, fee: FixedPoint) -> Self {
+        Self {
+            market_key: Pubkey::new_unique(),
+            tickmap_key: Pubkey::new_unique(),
+            token_x: Pubkey::new_unique(),
+            token_y: Pubkey::new_unique(),
+            tick_spacing,
+            fee,
+            current_tick_index: 0,
+            sqrt_price: None,
+            ticks: BTreeMap::new(),
+        }
+    }
+
+    pub fn mints(mut self, token_x: Pubkey, token_y: Pubkey) -> Self {
+        self.token_x = token_x;
+        self.token_y = token_y;
+        self
+    }
+
+    pub fn current_tick_index(mut self, current_tick_index: i32) -> Self {
+        self.current_tick_index = current_tick_index;
+        self
+    }
+
+    pub fn sqrt_price(mut self, sqrt_price: Price) -> Self {
+        self.sqrt_price = Some(sqrt_price);
+        self
+    }
+
+    // positive change adds liquidity when the price crosses the tick upwards
+    pub fn tick(mut self, index: i32, liquidity_change: i128) -> Self {
+        let (change, gross) = self.ticks.entry(index).or_insert((0, 0));
+        *change += liquidity_change;
+        *gross += liquidity_change.unsigned_abs();
+        self
+    }
+
+    pub fn position(self, lower_tick_index: i32, upper_tick_index: i32, liquidity: u128) -> Self {
+        let liquidity = liquidity as i128;
+        self.tick(lower_tick_index, liquidity)
+            .tick(upper_tick_index, -liquidity)
+    }
+
+    pub fn pool(&self) -> Result<Pool, InvariantError> {
+        let _ = self.check_tick_index(self.current_tick_index)?;
+        let liquidity = self
+            .ticks
+            .range(..=self.current_tick_index)
+            .try_fold(0i128, |liquidity, (_, (change, _))| {
+                liquidity.checked_add(*change)
+            })
+            .ok_or(InvariantError::MathOverflow("pool liquidity add"))?;
+        let liquidity = u128::try_from(liquidity)
+            .map_err(|_| InvariantError::MathError(String::from("negative pool liquidity")))?;
+
+        Ok(Pool {
+            token_x: self.token_x,
+            token_y: self.token_y,
+            tick_spacing: self.tick_spacing,
+            fee: self.fee,
+            liquidity: Liquidity::new(liquidity),
+            sqrt_price: self
+                .sqrt_price
+                .unwrap_or_else(|| calculate_price_sqrt(self.current_tick_index)),
+            current_tick_index: self.current_tick_index,
+            tickmap: self.tickmap_key,
+            ..Default::default()
+        })
+    }
+
+    pub fn tickmap(&self) -> Result<Tickmap, InvariantError> {
+        let mut tickmap = Tickmap::default();
+        for index in self.ticks.keys() {
+            let position = self.check_tick_index(*index)?;
+            tickmap.bitmap[position / 8] |= 1 << (position % 8);
+        }
+        Ok(tickmap)
+    }
+
+    pub fn tick_accounts(&self) -> Result<Vec<Tick>, InvariantError> {
+        self.ticks
+            .iter()
+            .map(|(index, (change, gross))| {
+                let _ = self.check_tick_index(*index)?;
+                Ok(Tick {
+                    pool: self.market_key,
+                    index: *index,
+                    sign: *change >= 0,
+                    liquidity_change: Liquidity::new(change.unsigned_abs()),
+                    liquidity_gross: Liquidity::new(*gross),
+                    sqrt_price: calculate_price_sqrt(*index),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    pub fn keyed_account(&self) -> Result<KeyedAccount, InvariantError> {
+        Ok(KeyedAccount {
+            key: self.market_key,
+            account: Account {
+                data: anchor_account_data(&self.pool()?),
+                owner: ID,
+                ..Account::default()
+            },
+            params: None,
+        })
+    }
+
+    // pool, tickmap and tick accounts as returned by the RPC
+    pub fn accounts_map(&self) -> Result<HashMap<Pubkey, Vec<u8>>, InvariantError> {
+        let jupiter_invariant = JupiterInvariant::new_from_keyed_account(&self.keyed_account()?)?;
+        let mut accounts_map: HashMap<Pubkey, Vec<u8>> = self
+            .tick_accounts()?
+            .iter()
+            .map(|tick| {
+                (
+                    jupiter_invariant.tick_index_to_address(tick.index),
+                    anchor_account_data(tick),
+                )
+            })
+            .collect();
+        let _ = accounts_map.insert(self.market_key, anchor_account_data(&self.pool()?));
+        let _ = accounts_map.insert(self.tickmap_key, anchor_account_data(&self.tickmap()?));
+
+        Ok(accounts_map)
+    }
+
+    pub fn build(&self) -> Result<JupiterInvariant, InvariantError> {
+        let mut jupiter_invariant =
+            JupiterInvariant::new_from_keyed_account(&self.keyed_account()?)?;
+        jupiter_invariant.update_accounts(&self.accounts_map()?)?;
+
+        Ok(jupiter_invariant)
+    }
+
+    // returns the bit position of the tick in the tickmap
+    fn check_tick_index(&self, index: i32) -> Result<usize, InvariantError> {
+        let tick_spacing: i32 = self.tick_spacing.into();
+        let violation = InvariantError::TickSpacingViolation {
+            tick_index: index,
+            tick_spacing: self.tick_spacing,
+        };
+        if tick_spacing == 0 || index % tick_spacing != 0 {
+            return Err(violation);
+        }
+        let position = index / tick_spacing + TICK_LIMIT;
+        match (0..2 * TICK_LIMIT).contains(&position) {
+            true => Ok(position as usize),
+            false => Err(violation),
+        }
+    }
+}
+
+// anchor account layout: discriminator followed by the borsh encoded account
+pub fn anchor_account_data<T: InvariantAccount + AnchorSerialize>(account: &T) -> Vec<u8> {
+    let mut data = T::discriminator().to_vec();
+    account
+        .serialize(&mut data)
+        .expect("serialization into a vector cannot fail");
+    data
+}
+
+// minimal JSON RPC server answering `getMultipleAccounts` and `getProgramAccounts` from an in-memory accounts map
+#[derive(Clone)]
+pub struct MockRpcServer {
+    url: String,
+    accounts: Arc<Mutex<HashMap<Pubkey, Vec<u8>>>>,
+    // number of keys in every received `getMultipleAccounts` request
+    requests: Arc<Mutex<Vec<usize>>>,
+}
+
+impl MockRpcServer {
+    pub fn start(accounts: HashMap<Pubkey, Vec<u8>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("cannot bind the mock RPC server");
+        let server = Self {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            accounts: Arc::new(Mutex::new(accounts)),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        };
+
+        let handler = server.clone();
+        let _ = thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handler = handler.clone();
+                let _ = thread::spawn(move || handler.serve(stream));
+            }
+        });
+        server
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    pub fn set_accounts(&self, accounts: HashMap<Pubkey, Vec<u8>>) {
+        *self.accounts.lock().unwrap() = accounts;
+    }
+
+    pub fn requests(&self) -> Vec<usize> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    // keeps the connection alive until the client closes it
+    fn serve(&self, stream: TcpStream) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        loop {
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                match reader.read_line(&mut line) {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {}
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            if reader.read_exact(&mut body).is_err() {
+                return;
+            }
+            let response = match serde_json::from_slice::<Value>(&body) {
+                Ok(request) => self.handle(&request).to_string(),
+                Err(e) => Self::error_response(Value::Null, -32700, &e.to_string()).to_string(),
+            };
+            let http_response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                response.len(),
+                response
+            );
+            if writer.write_all(http_response.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn handle(&self, request: &Value) -> Value {
+        let id = request["id"].clone();
+        let result = match request["method"].as_str() {
+            Some("getMultipleAccounts") => self.get_multiple_accounts(&request["params"]),
+            Some("getProgramAccounts") => self.get_program_accounts(&request["params"]),
+            Some("getVersion") => Ok(json!({"solana-core": "1.14.11", "feature-set": 0})),
+            _ => Err((-32601, "Method not found".to_string())),
+        };
+        match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => Self::error_response(id, code, &message),
+        }
+    }
+
+    fn get_multiple_accounts(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let keys = params[0]
+            .as_array()
+            .ok_or((-32602, "Invalid params".to_string()))?;
+        self.requests.lock().unwrap().push(keys.len());
+        if keys.len() > JupiterInvariant::MAX_MULTIPLE_ACCOUNTS {
+            return Err((-32602, "Too many inputs provided; max 100".to_string()));
+        }
+
+        let accounts = self.accounts.lock().unwrap();
+        let value: Vec<Value> = keys
+            .iter()
+            .map(|key| {
+                key.as_str()
+                    .and_then(|key| Pubkey::from_str(key).ok())
+                    .and_then(|key| accounts.get(&key))
+                    .map_or(Value::Null, |data| Self::account_json(data))
+            })
+            .collect();
+
+        Ok(json!({"context": {"slot": 1}, "value": value}))
+    }
+
+    // every account is owned by the Invariant program, only memcmp filters are supported
+    fn get_program_accounts(&self, params: &Value) -> Result<Value, (i64, String)> {
+        if params[0] != ID.to_string() {
+            return Ok(json!([]));
+        }
+        let filters = params[1]["filters"]
+            .as_array()
+            .map(|filters| {
+                filters
+                    .iter()
+                    .map(|filter| {
+                        let memcmp = &filter["memcmp"];
+                        let offset = memcmp["offset"].as_u64().unwrap_or(0) as usize;
+                        let bytes = memcmp["bytes"].as_str().unwrap_or_default();
+                        let bytes = match memcmp["encoding"].as_str() {
+                            Some("base64") => STANDARD.decode(bytes).ok(),
+                            _ => bs58::decode(bytes).into_vec().ok(),
+                        };
+                        bytes
+                            .map(|bytes| (offset, bytes))
+                            .ok_or((-32602, "Invalid filter".to_string()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let accounts = self.accounts.lock().unwrap();
+        let value: Vec<Value> = accounts
+            .iter()
+            .filter(|(_, data)| {
+                filters.iter().all(|(offset, bytes)| {
+                    data.get(*offset..offset + bytes.len()) == Some(bytes.as_slice())
+                })
+            })
+            .map(|(key, data)| json!({"pubkey": key.to_string(), "account": Self::account_json(data)}))
+            .collect();
+
+        Ok(json!(value))
+    }
+
+    fn account_json(data: &[u8]) -> Value {
+        json!({
+            "data": [STANDARD.encode(data), "base64"],
+            "executable": false,
+            "lamports": 1_000_000_000u64,
+            "owner": ID.to_string(),
+            "rentEpoch": 0,
+        })
+    }
+
+    fn error_response(id: Value, code: i64, message: &str) -> Value {
+        json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use invariant_types::{
+        decimals::{Decimal, FixedPoint, Liquidity},
+        structs::TICK_LIMIT,
+    };
+
+    use crate::{error::InvariantError, test_utils::PoolBuilder};
+
+    #[test]
+    fn test_build_synthetic_pool() {
+        let builder = PoolBuilder::new(10, FixedPoint::new(100000000))
+            .position(-100, 100, 1000)
+            .position(-20, 20, 500)
+            .position(50, 200, 300);
+        let jupiter_invariant = builder.build().unwrap();
+
+        assert_eq!(jupiter_invariant.market_key, builder.market_key);
+        assert_eq!({ jupiter_invariant.pool.liquidity }, Liquidity::new(1500));
+        assert_eq!(jupiter_invariant.ticks.len(), 6);
+        assert!(!jupiter_invariant.ticks_accounts_outdated().unwrap());
+
+        let tick = jupiter_invariant
+            .ticks
+            .get(&jupiter_invariant.tick_index_to_address(100))
+            .unwrap();
+        assert!(!{ tick.sign });
+        assert_eq!({ tick.liquidity_change }, Liquidity::new(1000));
+
+        let position = (100 / 10 + TICK_LIMIT) as usize;
+        assert_ne!(
+            jupiter_invariant.tickmap.bitmap[position / 8] & (1 << (position % 8)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_build_invalid_synthetic_pool() {
+        // misaligned tick
+        {
+            let result = PoolBuilder::new(10, FixedPoint::new(0))
+                .position(-15, 10, 1000)
+                .build();
+            assert_eq!(
+                result.err(),
+                Some(InvariantError::TickSpacingViolation {
+                    tick_index: -15,
+                    tick_spacing: 10
+                })
+            );
+        }
+        // negative pool liquidity
+        {
+            let result = PoolBuilder::new(10, FixedPoint::new(0))
+                .tick(-10, -1000)
+                .build();
+            assert!(result.is_err());
+        }
+    }
+}
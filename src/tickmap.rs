@@ -0,0 +1,202 @@
+use invariant_types::structs::TICKMAP_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriceDirection {
+    UP,
+    DOWN,
+}
+
+// positions of the closest initialized ticks in ascending order, searching above the position
+// or below it (inclusive), positions outside of the bitmap are treated as uninitialized
+pub fn find_closest_positions(
+    bitmap: &[u8],
+    position: i32,
+    amount_limit: usize,
+    direction: PriceDirection,
+) -> Vec<i32> {
+    let last_position = (bitmap.len() * 8) as i32 - 1;
+    let mut found: Vec<i32> = Vec::with_capacity(amount_limit);
+
+    match direction {
+        PriceDirection::UP => {
+            let (from, to) = (
+                position.saturating_add(1).max(0),
+                TICKMAP_SIZE.min(last_position),
+            );
+            if from > to || amount_limit == 0 {
+                return found;
+            }
+            let mut word_index = from as usize / 64;
+            let mut word = load_word(bitmap, word_index) & (u64::MAX << (from % 64));
+            'search: loop {
+                while word != 0 {
+                    let found_position = (word_index * 64) as i32 + word.trailing_zeros() as i32;
+                    if found_position > to {
+                        break 'search;
+                    }
+                    found.push(found_position);
+                    if found.len() == amount_limit {
+                        break 'search;
+                    }
+                    word &= word - 1;
+                }
+                word_index += 1;
+                if (word_index * 64) as i32 > to {
+                    break;
+                }
+                word = load_word(bitmap, word_index);
+            }
+        }
+        PriceDirection::DOWN => {
+            let from = position.min(last_position);
+            if from < 0 || amount_limit == 0 {
+                return found;
+            }
+            let mut word_index = from as usize / 64;
+            let mut word = load_word(bitmap, word_index) & (u64::MAX >> (63 - from % 64));
+            'search: loop {
+                while word != 0 {
+                    let bit = 63 - word.leading_zeros();
+                    found.push((word_index * 64) as i32 + bit as i32);
+                    if found.len() == amount_limit {
+                        break 'search;
+                    }
+                    word &= !(1u64 << bit);
+                }
+                if word_index == 0 {
+                    break;
+                }
+                word_index -= 1;
+                word = load_word(bitmap, word_index);
+            }
+            found.reverse();
+        }
+    }
+
+    found
+}
+
+// reference implementation testing the bitmap bit by bit
+pub fn find_closest_positions_bitwise(
+    bitmap: &[u8],
+    position: i32,
+    amount_limit: usize,
+    direction: PriceDirection,
+) -> Vec<i32> {
+    let is_initialized = |index: i32| {
+        index >= 0
+            && bitmap
+                .get((index / 8) as usize)
+                .map_or(false, |byte| byte & (1 << (index % 8)) != 0)
+    };
+    let mut found: Vec<i32> = Vec::new();
+    let (mut above, mut below, mut reached_limit) = (position + 1, position, false);
+
+    while !reached_limit && found.len() < amount_limit {
+        match direction {
+            PriceDirection::UP => {
+                if is_initialized(above) {
+                    found.push(above);
+                }
+                reached_limit = above >= TICKMAP_SIZE;
+                above += 1;
+            }
+            PriceDirection::DOWN => {
+                if is_initialized(below) {
+                    found.insert(0, below);
+                }
+                reached_limit = below <= 0;
+                below -= 1;
+            }
+        }
+    }
+
+    found
+}
+
+// little endian word, so the bit `n` of the word is the position `word_index * 64 + n`
+fn load_word(bitmap: &[u8], word_index: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    let start = word_index * 8;
+    if start < bitmap.len() {
+        let end = bitmap.len().min(start + 8);
+        bytes[..end - start].copy_from_slice(&bitmap[start..end]);
+    }
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use invariant_types::structs::{Tickmap, TICKMAP_SIZE, TICK_LIMIT};
+
+    use crate::tickmap::{find_closest_positions, find_closest_positions_bitwise, PriceDirection};
+
+    fn assert_same_as_bitwise(bitmap: &[u8], position: i32, amount_limit: usize) {
+        for direction in [PriceDirection::UP, PriceDirection::DOWN] {
+            assert_eq!(
+                find_closest_positions(bitmap, position, amount_limit, direction),
+                find_closest_positions_bitwise(bitmap, position, amount_limit, direction),
+                "position: {}, limit: {}, direction: {:?}",
+                position,
+                amount_limit,
+                direction
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_closest_positions_edges() {
+        let mut tickmap = Tickmap::default();
+        let last_position = (tickmap.bitmap.len() * 8) as i32 - 1;
+        for position in [0, 1, 63, 64, 65, TICK_LIMIT, TICKMAP_SIZE, last_position] {
+            let position = position.min(last_position);
+            tickmap.bitmap[position as usize / 8] |= 1 << (position % 8);
+        }
+
+        for position in [
+            -2 * TICK_LIMIT,
+            -1,
+            0,
+            1,
+            62,
+            63,
+            64,
+            127,
+            128,
+            TICK_LIMIT,
+            TICKMAP_SIZE - 1,
+            TICKMAP_SIZE,
+            last_position,
+            3 * TICK_LIMIT,
+        ] {
+            for amount_limit in [0, 1, 2, 3, 10] {
+                assert_same_as_bitwise(&tickmap.bitmap, position, amount_limit);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_closest_positions_random_bitmaps() {
+        // xorshift, deterministic across runs
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for density in [2, 64, 4096] {
+            let mut tickmap = Tickmap::default();
+            let bits = tickmap.bitmap.len() as u64 * 8;
+            for _ in 0..bits / density {
+                let position = next() % bits;
+                tickmap.bitmap[(position / 8) as usize] |= 1 << (position % 8);
+            }
+            for _ in 0..50 {
+                let position = (next() % bits) as i32;
+                assert_same_as_bitwise(&tickmap.bitmap, position, 20);
+            }
+        }
+    }
+}
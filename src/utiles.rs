@@ -1,47 +1,146 @@
 use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
 
 use anchor_lang::Key;
 use anchor_lang::{prelude::Pubkey, AnchorDeserialize};
 use invariant_types::decimals::{BigOps, Decimal, Price, U256};
+use invariant_types::log::get_tick_at_sqrt_price;
+use invariant_types::math::{
+    calculate_price_sqrt, get_max_sqrt_price, get_max_tick, get_min_sqrt_price, get_min_tick,
+};
 use invariant_types::{
-    structs::{TICKMAP_SIZE, TICK_CROSSES_PER_IX, TICK_LIMIT},
+    structs::{Pool, Tick, Tickmap, TICK_LIMIT},
     ANCHOR_DISCRIMINATOR_SIZE, MAX_SQRT_PRICE, TICK_SEED,
 };
-use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
 use solana_client::rpc_client::RpcClient;
 
-use crate::JupiterInvariant;
+use crate::{
+    error::InvariantError,
+    tickmap::{find_closest_positions, PriceDirection},
+    JupiterInvariant, TicksWindow,
+};
+
+// (program id, market, tick index) -> tick address, shared between the clones of the pool
+// the program and the market are part of the key as both can be rewritten on a clone
+pub type TickAddressKey = (Pubkey, Pubkey, i32);
+
+#[derive(Clone, Debug, Default)]
+pub struct TickAddressCache(Arc<RwLock<HashMap<TickAddressKey, Pubkey>>>);
+
+impl TickAddressCache {
+    pub fn get_or_insert_with(
+        &self,
+        key: TickAddressKey,
+        derive: impl FnOnce() -> Pubkey,
+    ) -> Pubkey {
+        let cached = self
+            .0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+            .copied();
+        cached.unwrap_or_else(|| {
+            let address = derive();
+            let _ = self
+                .0
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(key, address);
+            address
+        })
+    }
+
+    // keeps the addresses accepted by `keep`, the rest is derived again on the next lookup
+    pub fn retain(&self, mut keep: impl FnMut(&Pubkey) -> bool) {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|_, address| keep(address));
+    }
 
-enum PriceDirection {
-    UP,
-    DOWN,
+    pub fn len(&self) -> usize {
+        self.0.read().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// accounts of the Invariant program recognized by their Anchor discriminator
+pub trait InvariantAccount: AnchorDeserialize {
+    const NAME: &'static str;
+    // sha256("account:<name>")[..8], precomputed as it is checked on every deserialization
+    const DISCRIMINATOR: [u8; ANCHOR_DISCRIMINATOR_SIZE];
+
+    fn discriminator() -> [u8; ANCHOR_DISCRIMINATOR_SIZE] {
+        Self::DISCRIMINATOR
+    }
+}
+
+impl InvariantAccount for Pool {
+    const NAME: &'static str = "Pool";
+    const DISCRIMINATOR: [u8; ANCHOR_DISCRIMINATOR_SIZE] = [241, 154, 109, 4, 17, 177, 109, 188];
+}
+
+impl InvariantAccount for Tickmap {
+    const NAME: &'static str = "Tickmap";
+    const DISCRIMINATOR: [u8; ANCHOR_DISCRIMINATOR_SIZE] = [236, 6, 101, 196, 85, 189, 0, 227];
+}
+
+impl InvariantAccount for Tick {
+    const NAME: &'static str = "Tick";
+    const DISCRIMINATOR: [u8; ANCHOR_DISCRIMINATOR_SIZE] = [176, 94, 67, 247, 133, 173, 7, 115];
 }
 
 impl JupiterInvariant {
     pub const PRICE_IMPACT_ACCURACY: u128 = 1_000_000_000_000u128;
 
-    pub fn deserialize<T>(data: &[u8]) -> anyhow::Result<T>
+    pub fn deserialize<T>(key: &Pubkey, data: &[u8]) -> Result<T, InvariantError>
     where
-        T: AnchorDeserialize,
+        T: InvariantAccount,
     {
-        T::try_from_slice(Self::extract_from_anchor_account(data))
-            .map_err(|e| anyhow::anyhow!("Error deserializing account data: {:?}", e))
+        let account_data = Self::extract_from_anchor_account(data).ok_or_else(|| {
+            InvariantError::DeserializationFailed {
+                key: *key,
+                reason: String::from("account data shorter than the discriminator"),
+            }
+        })?;
+        if data[..ANCHOR_DISCRIMINATOR_SIZE] != T::discriminator() {
+            return Err(InvariantError::InvalidDiscriminator {
+                key: *key,
+                expected: T::NAME,
+            });
+        }
+        T::try_from_slice(account_data).map_err(|e| InvariantError::DeserializationFailed {
+            key: *key,
+            reason: e.to_string(),
+        })
+    }
+
+    pub fn check_owner(&self, key: &Pubkey, owner: &Pubkey) -> Result<(), InvariantError> {
+        match owner.eq(&self.program_id) {
+            true => Ok(()),
+            false => Err(InvariantError::InvalidOwner {
+                key: *key,
+                owner: *owner,
+            }),
+        }
     }
 
     pub fn fetch_accounts(
         rpc: &RpcClient,
         accounts_to_update: Vec<Pubkey>,
-    ) -> HashMap<Pubkey, Vec<u8>> {
-        rpc.get_multiple_accounts(&accounts_to_update)
-            .unwrap()
-            .iter()
-            .enumerate()
-            .fold(HashMap::new(), |mut m, (index, account)| {
-                if let Some(account) = account {
-                    let _ = m.insert(accounts_to_update[index], account.data.clone());
-                }
-                m
-            })
+    ) -> Result<HashMap<Pubkey, Vec<u8>>, InvariantError> {
+        let mut accounts_map = HashMap::new();
+        for keys in accounts_to_update.chunks(Self::MAX_MULTIPLE_ACCOUNTS) {
+            let accounts = rpc
+                .get_multiple_accounts(keys)
+                .map_err(|e| InvariantError::RpcError(e.to_string()))?;
+            Self::insert_fetched_accounts(&mut accounts_map, keys, accounts);
+        }
+        Ok(accounts_map)
     }
 
     pub fn tick_indexes_to_addresses(&self, indexes: &[i32]) -> Vec<Pubkey> {
@@ -53,91 +152,95 @@ impl JupiterInvariant {
     }
 
     pub fn tick_index_to_address(&self, i: i32) -> Pubkey {
-        let (pubkey, _) = Pubkey::find_program_address(
-            &[
-                TICK_SEED.as_bytes(),
-                self.market_key.key().as_ref(),
-                &i.to_le_bytes(),
-            ],
-            &self.program_id,
-        );
-        pubkey
+        let key = (self.program_id, self.market_key, i);
+        self.tick_addresses.get_or_insert_with(key, || {
+            let (pubkey, _) = Pubkey::find_program_address(
+                &[
+                    TICK_SEED.as_bytes(),
+                    self.market_key.key().as_ref(),
+                    &i.to_le_bytes(),
+                ],
+                &self.program_id,
+            );
+            pubkey
+        })
     }
 
-    pub fn get_ticks_addresses_around(&self) -> Vec<Pubkey> {
-        let above_indexes = self.find_closest_tick_indexes(TICK_CROSSES_PER_IX, PriceDirection::UP);
-        let below_indexes =
-            self.find_closest_tick_indexes(TICK_CROSSES_PER_IX, PriceDirection::DOWN);
+    pub fn get_ticks_addresses_around(&self) -> Result<Vec<Pubkey>, InvariantError> {
+        let TicksWindow {
+            up,
+            down,
+            opposite_tick,
+        } = self.ticks_window;
+        // the tick against the swap direction has to be cached for both directions
+        let (up, down) = match opposite_tick {
+            true => (up.max(1), down.max(1)),
+            false => (up, down),
+        };
+        let above_indexes = self.find_closest_tick_indexes(up, PriceDirection::UP)?;
+        let below_indexes = self.find_closest_tick_indexes(down, PriceDirection::DOWN)?;
         let all_indexes = [below_indexes, above_indexes].concat();
 
-        self.tick_indexes_to_addresses(&all_indexes)
+        Ok(self.tick_indexes_to_addresses(&all_indexes))
+    }
+
+    // closest initialized tick against the swap direction
+    pub fn opposite_tick_address(&self, x_to_y: bool) -> Result<Option<Pubkey>, InvariantError> {
+        let direction = match x_to_y {
+            true => PriceDirection::UP,
+            false => PriceDirection::DOWN,
+        };
+        let indexes = self.find_closest_tick_indexes(1, direction)?;
+
+        Ok(indexes
+            .first()
+            .map(|index| self.tick_index_to_address(*index)))
     }
 
-    pub fn ticks_accounts_outdated(&self) -> bool {
-        let ticks_addresses = self.get_ticks_addresses_around();
+    pub fn ticks_accounts_outdated(&self) -> Result<bool, InvariantError> {
+        let ticks_addresses = self.get_ticks_addresses_around()?;
 
-        ticks_addresses
+        Ok(ticks_addresses
             .iter()
-            .any(|address| !self.ticks.contains_key(address))
+            .any(|address| !self.ticks.contains_key(address)))
     }
 
-    fn extract_from_anchor_account(data: &[u8]) -> &[u8] {
-        data.split_at(ANCHOR_DISCRIMINATOR_SIZE).1
+    fn extract_from_anchor_account(data: &[u8]) -> Option<&[u8]> {
+        data.get(ANCHOR_DISCRIMINATOR_SIZE..)
     }
 
-    fn find_closest_tick_indexes(
+    pub(crate) fn find_closest_tick_indexes(
         &self,
         amount_limit: usize,
         direction: PriceDirection,
-    ) -> Vec<i32> {
+    ) -> Result<Vec<i32>, InvariantError> {
         let current: i32 = self.pool.current_tick_index;
         let tick_spacing: i32 = self.pool.tick_spacing.into();
-        let tickmap = self.tickmap.bitmap;
+        let tickmap = &self.tickmap.bitmap;
 
-        if current % tick_spacing != 0 {
-            panic!("Invalid arguments: can't find initialized ticks")
+        if tick_spacing == 0 || current % tick_spacing != 0 {
+            return Err(InvariantError::TickSpacingViolation {
+                tick_index: current,
+                tick_spacing: self.pool.tick_spacing,
+            });
         }
-        let mut found: Vec<i32> = Vec::new();
         let current_index = current / tick_spacing + TICK_LIMIT;
-        let (mut above, mut below, mut reached_limit) = (current_index + 1, current_index, false);
-
-        while !reached_limit && found.len() < amount_limit {
-            match direction {
-                PriceDirection::UP => {
-                    let value_above: u8 =
-                        *tickmap.get((above / 8) as usize).unwrap() & (1 << (above % 8));
-                    if value_above != 0 {
-                        found.push(above);
-                    }
-                    reached_limit = above >= TICKMAP_SIZE;
-                    above += 1;
-                }
-                PriceDirection::DOWN => {
-                    let value_below: u8 =
-                        *tickmap.get((below / 8) as usize).unwrap() & (1 << (below % 8));
-                    if value_below != 0 {
-                        found.insert(0, below);
-                    }
-                    reached_limit = below <= 0;
-                    below -= 1;
-                }
-            }
-        }
+        let found = find_closest_positions(tickmap, current_index, amount_limit, direction);
 
-        found
+        Ok(found
             .iter()
             .map(|i: &i32| (i - TICK_LIMIT) * tick_spacing)
-            .collect()
+            .collect())
     }
 
     pub fn calculate_price_impact(
         starting_sqrt_price: Price,
         ending_sqrt_price: Price,
-    ) -> Result<rust_decimal::Decimal, &'static str> {
+    ) -> Result<rust_decimal::Decimal, InvariantError> {
         if starting_sqrt_price > Price::new(MAX_SQRT_PRICE)
             || ending_sqrt_price > Price::new(MAX_SQRT_PRICE)
         {
-            return Err("Price out of range");
+            return Err(InvariantError::PriceOutOfRange);
         }
 
         let accuracy = U256::from(Self::PRICE_IMPACT_ACCURACY);
@@ -151,25 +254,450 @@ impl JupiterInvariant {
         let price_quote = accuracy
             .checked_mul(numerator)
             .and_then(|result| result.checked_div(denominator))
-            .ok_or("mul/div overflow")?;
+            .ok_or(InvariantError::MathOverflow("price quote mul/div"))?;
 
-        let price_impact_decimal = accuracy.checked_sub(price_quote).ok_or("sub overflow")?;
+        let price_impact_decimal = accuracy
+            .checked_sub(price_quote)
+            .ok_or(InvariantError::MathOverflow("price impact sub"))?;
         let price_impact_pct = f64::from_u128(price_impact_decimal.as_u128())
-            .ok_or_else(|| "converting price impact to f64")?
+            .ok_or(InvariantError::ConversionError("price impact to f64"))?
             / f64::from_u128(Self::PRICE_IMPACT_ACCURACY)
-                .ok_or_else(|| "converting accuracy to f64")?;
+                .ok_or(InvariantError::ConversionError("accuracy to f64"))?;
+
+        Ok(rust_decimal::Decimal::from_f64(price_impact_pct).ok_or(
+            InvariantError::ConversionError("price impact to rust_decimal"),
+        )?)
+    }
+
+    // price is the amount of token y for one token x, both expressed in UI units
+    pub fn price_to_sqrt_price(
+        price: rust_decimal::Decimal,
+        decimals_x: u8,
+        decimals_y: u8,
+    ) -> Result<Price, InvariantError> {
+        if price.is_sign_negative() || price.is_zero() {
+            return Err(InvariantError::PriceOutOfRange);
+        }
+
+        // sqrt_price = sqrt(price * 10^(decimals_y - decimals_x)) * 10^PRICE_SCALE
+        let exponent = 2 * Price::scale() as i32 - price.scale() as i32 + decimals_y as i32
+            - decimals_x as i32;
+        let scaled_price =
+            Self::mul_by_power_of_ten(U256::from(price.mantissa() as u128), exponent)
+                .ok_or(InvariantError::PriceOutOfRange)?;
+
+        Ok(Price::new(scaled_price.integer_sqrt().as_u128()))
+    }
+
+    // inverse of `price_to_sqrt_price`, rounded down to the precision of `rust_decimal::Decimal`
+    pub fn sqrt_price_to_price(
+        sqrt_price: Price,
+        decimals_x: u8,
+        decimals_y: u8,
+    ) -> Result<rust_decimal::Decimal, InvariantError> {
+        // price = sqrt_price^2 * 10^(decimals_x - decimals_y) / 10^(2 * PRICE_SCALE)
+        let sqrt_price = U256::from(sqrt_price.get());
+        let mut value = sqrt_price
+            .checked_mul(sqrt_price)
+            .ok_or(InvariantError::MathOverflow("sqrt price square"))?;
+        let mut scale = 2 * Price::scale() as i32 + decimals_y as i32 - decimals_x as i32;
+        if scale < 0 {
+            value =
+                Self::mul_by_power_of_ten(value, -scale).ok_or(InvariantError::PriceOutOfRange)?;
+            scale = 0;
+        }
+
+        // the decimal mantissa has 96 bits and the scale is at most 28
+        let max_mantissa = U256::from(u128::MAX >> 32);
+        while scale > 28 || value > max_mantissa {
+            if scale == 0 {
+                return Err(InvariantError::PriceOutOfRange);
+            }
+            value /= 10;
+            scale -= 1;
+        }
+
+        Ok(
+            rust_decimal::Decimal::from_i128_with_scale(value.as_u128() as i128, scale as u32)
+                .normalize(),
+        )
+    }
+
+    // price of token y denominated in token x, the reciprocal of `sqrt_price_to_price`
+    pub fn sqrt_price_to_inverse_price(
+        sqrt_price: Price,
+        decimals_x: u8,
+        decimals_y: u8,
+    ) -> Result<rust_decimal::Decimal, InvariantError> {
+        Self::sqrt_price_to_price(Self::invert_sqrt_price(sqrt_price)?, decimals_y, decimals_x)
+    }
+
+    // inverse price is the amount of token x for one token y, both expressed in UI units
+    pub fn inverse_price_to_sqrt_price(
+        inverse_price: rust_decimal::Decimal,
+        decimals_x: u8,
+        decimals_y: u8,
+    ) -> Result<Price, InvariantError> {
+        Self::invert_sqrt_price(Self::price_to_sqrt_price(
+            inverse_price,
+            decimals_y,
+            decimals_x,
+        )?)
+    }
+
+    pub fn tick_index_to_price(
+        tick_index: i32,
+        decimals_x: u8,
+        decimals_y: u8,
+    ) -> Result<rust_decimal::Decimal, InvariantError> {
+        let min_tick = get_min_tick(1).map_err(|err| InvariantError::MathError(err.cause))?;
+        let max_tick = get_max_tick(1).map_err(|err| InvariantError::MathError(err.cause))?;
+        if !(min_tick..=max_tick).contains(&tick_index) {
+            return Err(InvariantError::PriceOutOfRange);
+        }
+
+        Self::sqrt_price_to_price(calculate_price_sqrt(tick_index), decimals_x, decimals_y)
+    }
+
+    // closest tick index divisible by the spacing at or below the price
+    pub fn price_to_tick_index(
+        price: rust_decimal::Decimal,
+        decimals_x: u8,
+        decimals_y: u8,
+        tick_spacing: u16,
+    ) -> Result<i32, InvariantError> {
+        if tick_spacing == 0 {
+            return Err(InvariantError::TickSpacingViolation {
+                tick_index: 0,
+                tick_spacing,
+            });
+        }
+        let sqrt_price = Self::price_to_sqrt_price(price, decimals_x, decimals_y)?;
+        let min_sqrt_price =
+            get_min_sqrt_price(tick_spacing).map_err(|_| InvariantError::PriceOutOfRange)?;
+        let max_sqrt_price =
+            get_max_sqrt_price(tick_spacing).map_err(|_| InvariantError::PriceOutOfRange)?;
+        if sqrt_price < min_sqrt_price || sqrt_price > max_sqrt_price {
+            return Err(InvariantError::PriceOutOfRange);
+        }
 
-        Ok(rust_decimal::Decimal::from_f64(price_impact_pct)
-            .ok_or_else(|| "converting to rust_decimal")?)
+        Ok(get_tick_at_sqrt_price(sqrt_price, tick_spacing))
+    }
+
+    // amount of a mint with the given decimals expressed in UI units
+    pub fn amount_to_ui(
+        amount: u64,
+        decimals: u8,
+    ) -> Result<rust_decimal::Decimal, InvariantError> {
+        if decimals as u32 > rust_decimal::Decimal::MAX_PRECISION {
+            return Err(InvariantError::ConversionError(
+                "mint decimals to decimal scale",
+            ));
+        }
+        Ok(rust_decimal::Decimal::from_i128_with_scale(
+            amount as i128,
+            decimals as u32,
+        ))
+    }
+
+    // inverse of `amount_to_ui`, fractions of the smallest unit are rounded down
+    pub fn ui_to_amount(
+        ui_amount: rust_decimal::Decimal,
+        decimals: u8,
+    ) -> Result<u64, InvariantError> {
+        if ui_amount.is_sign_negative() {
+            return Err(InvariantError::ConversionError("negative UI amount"));
+        }
+        let unit = Self::amount_to_ui(1, decimals)?;
+        ui_amount
+            .checked_div(unit)
+            .and_then(|amount| amount.trunc().to_u64())
+            .ok_or(InvariantError::ConversionError("UI amount to u64"))
+    }
+
+    pub fn price_impact_to_sqrt_price_limit(
+        &self,
+        max_price_impact: rust_decimal::Decimal,
+        x_to_y: bool,
+    ) -> Result<Price, InvariantError> {
+        if max_price_impact <= rust_decimal::Decimal::ZERO
+            || max_price_impact >= rust_decimal::Decimal::ONE
+        {
+            return Err(InvariantError::InvalidPriceImpact);
+        }
+
+        // sqrt(1 - price_impact) * 10^PRICE_SCALE
+        let price_quote = rust_decimal::Decimal::ONE - max_price_impact;
+        let exponent = 2 * Price::scale() as i32 - price_quote.scale() as i32;
+        let sqrt_price_quote =
+            Self::mul_by_power_of_ten(U256::from(price_quote.mantissa() as u128), exponent)
+                .ok_or(InvariantError::InvalidPriceImpact)?
+                .integer_sqrt();
+
+        let one = U256::exp10(Price::scale() as usize);
+        let sqrt_price = U256::from(self.pool.sqrt_price.get());
+        let sqrt_price_limit = match x_to_y {
+            // rounding towards the starting price, so the price impact never exceeds the max
+            true => sqrt_price
+                .checked_mul(sqrt_price_quote)
+                .and_then(|result| result.checked_add(one - 1))
+                .and_then(|result| result.checked_div(one)),
+            false => sqrt_price
+                .checked_mul(one)
+                .and_then(|result| result.checked_div(sqrt_price_quote)),
+        }
+        .ok_or(InvariantError::MathOverflow("sqrt price limit mul/div"))?;
+
+        if x_to_y {
+            let min_sqrt_price = get_min_sqrt_price(self.pool.tick_spacing)
+                .map_err(|_| InvariantError::PriceOutOfRange)?;
+            Ok(Price::new(
+                sqrt_price_limit
+                    .max(U256::from(min_sqrt_price.get()))
+                    .as_u128(),
+            ))
+        } else {
+            let max_sqrt_price = get_max_sqrt_price(self.pool.tick_spacing)
+                .map_err(|_| InvariantError::PriceOutOfRange)?;
+            Ok(Price::new(
+                sqrt_price_limit
+                    .min(U256::from(max_sqrt_price.get()))
+                    .as_u128(),
+            ))
+        }
+    }
+
+    // 1 / sqrt_price, the square root of the price of token y in token x
+    fn invert_sqrt_price(sqrt_price: Price) -> Result<Price, InvariantError> {
+        if sqrt_price.get() == 0 {
+            return Err(InvariantError::PriceOutOfRange);
+        }
+        let inverse = U256::exp10(2 * Price::scale() as usize) / U256::from(sqrt_price.get());
+        match inverse > U256::from(u128::MAX) {
+            true => Err(InvariantError::PriceOutOfRange),
+            false => Ok(Price::new(inverse.as_u128())),
+        }
+    }
+
+    fn mul_by_power_of_ten(value: U256, exponent: i32) -> Option<U256> {
+        // 10^77 is the greatest power of ten that fits in U256
+        let power = exponent.unsigned_abs() as usize;
+        if exponent >= 0 {
+            match power > 77 {
+                true => None,
+                false => value.checked_mul(U256::exp10(power)),
+            }
+        } else {
+            match power > 77 {
+                true => Some(U256::zero()),
+                false => value.checked_div(U256::exp10(power)),
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use invariant_types::decimals::{Decimal, Factories, Price};
+    use std::collections::HashMap;
+
+    use anchor_lang::{prelude::Pubkey, solana_program::hash::hash};
+    use invariant_types::decimals::{Decimal, Factories, FixedPoint, Liquidity, Price};
+    use invariant_types::structs::{Pool, Tick, Tickmap, TICK_CROSSES_PER_IX, TICK_LIMIT};
+    use invariant_types::ANCHOR_DISCRIMINATOR_SIZE;
+    use jupiter_core::amm::{Amm, QuoteParams};
     use rust_decimal::prelude::FromPrimitive;
 
-    use crate::JupiterInvariant;
+    use super::{InvariantAccount, PriceDirection};
+    use crate::{error::InvariantError, test_utils::PoolBuilder, JupiterInvariant, TicksWindow};
+
+    #[test]
+    fn test_price_to_sqrt_price() {
+        // 6
+        {
+            let price = rust_decimal::Decimal::from(6);
+            let result = JupiterInvariant::price_to_sqrt_price(price, 6, 6).unwrap();
+            assert_eq!(result, Price::new(2449489742783178098197284));
+        }
+        // 0.2
+        {
+            let price = rust_decimal::Decimal::new(2, 1);
+            let result = JupiterInvariant::price_to_sqrt_price(price, 9, 9).unwrap();
+            assert_eq!(result, Price::new(447213595499957939281834));
+        }
+        // 1 with different decimals (raw price 0.001)
+        {
+            let price = rust_decimal::Decimal::ONE;
+            let result = JupiterInvariant::price_to_sqrt_price(price, 9, 6).unwrap();
+            assert_eq!(result, Price::new(31622776601683793319988));
+        }
+        // non positive price
+        {
+            let result = JupiterInvariant::price_to_sqrt_price(rust_decimal::Decimal::ZERO, 6, 6);
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_sqrt_price_to_price() {
+        // 6
+        {
+            let sqrt_price = Price::new(2449489742783178098197284);
+            let result = JupiterInvariant::sqrt_price_to_price(sqrt_price, 6, 6).unwrap();
+            assert_eq!(result.round_dp(12), rust_decimal::Decimal::from(6));
+        }
+        // 1 with different decimals (raw price 1 is 1000 in UI units)
+        {
+            let result =
+                JupiterInvariant::sqrt_price_to_price(Price::from_integer(1), 9, 6).unwrap();
+            assert_eq!(result, rust_decimal::Decimal::from(1000));
+        }
+        // round trip
+        {
+            let price = rust_decimal::Decimal::new(123456, 4);
+            let sqrt_price = JupiterInvariant::price_to_sqrt_price(price, 8, 6).unwrap();
+            let result = JupiterInvariant::sqrt_price_to_price(sqrt_price, 8, 6).unwrap();
+            assert_eq!(result.round_dp(10), price);
+        }
+    }
+
+    #[test]
+    fn test_inverse_price() {
+        // 4 of token y for token x is 0.25 of token x for token y
+        {
+            let sqrt_price = Price::from_integer(2);
+            let result = JupiterInvariant::sqrt_price_to_inverse_price(sqrt_price, 6, 6).unwrap();
+            assert_eq!(result, rust_decimal::Decimal::new(25, 2));
+        }
+        // different decimals
+        {
+            let price = rust_decimal::Decimal::new(25, 0);
+            let sqrt_price = JupiterInvariant::price_to_sqrt_price(price, 9, 6).unwrap();
+            let result = JupiterInvariant::sqrt_price_to_inverse_price(sqrt_price, 9, 6).unwrap();
+            assert_eq!(result.round_dp(12), rust_decimal::Decimal::new(4, 2));
+
+            let inverse_sqrt_price =
+                JupiterInvariant::inverse_price_to_sqrt_price(result, 9, 6).unwrap();
+            let price_result =
+                JupiterInvariant::sqrt_price_to_price(inverse_sqrt_price, 9, 6).unwrap();
+            assert_eq!(price_result.round_dp(10), price);
+        }
+        // zero
+        {
+            let result = JupiterInvariant::sqrt_price_to_inverse_price(Price::new(0), 6, 6);
+            assert_eq!(result, Err(InvariantError::PriceOutOfRange));
+        }
+    }
+
+    #[test]
+    fn test_tick_index_price() {
+        let margin = rust_decimal::Decimal::new(1, 9);
+        for tick_index in [-221818, -1000, -10, 0, 10, 12345, 221818] {
+            let price = JupiterInvariant::tick_index_to_price(tick_index, 6, 9).unwrap();
+            let above = price * (rust_decimal::Decimal::ONE + margin);
+            let below = price * (rust_decimal::Decimal::ONE - margin);
+            if tick_index < 221818 {
+                assert_eq!(
+                    JupiterInvariant::price_to_tick_index(above, 6, 9, 1).unwrap(),
+                    tick_index
+                );
+            }
+            if tick_index > -221818 {
+                assert_eq!(
+                    JupiterInvariant::price_to_tick_index(below, 6, 9, 1).unwrap(),
+                    tick_index - 1
+                );
+            }
+        }
+        // rounded down to the tick spacing
+        {
+            let price = JupiterInvariant::tick_index_to_price(15, 6, 6).unwrap();
+            assert_eq!(
+                JupiterInvariant::price_to_tick_index(price, 6, 6, 10).unwrap(),
+                10
+            );
+            let price = JupiterInvariant::tick_index_to_price(-15, 6, 6).unwrap();
+            assert_eq!(
+                JupiterInvariant::price_to_tick_index(price, 6, 6, 10).unwrap(),
+                -20
+            );
+        }
+        // 1 of token x (9 decimals) for 1000 of token y (6 decimals) is the raw price 1
+        assert_eq!(
+            JupiterInvariant::tick_index_to_price(0, 9, 6).unwrap(),
+            rust_decimal::Decimal::from(1000)
+        );
+        // out of range
+        assert!(JupiterInvariant::tick_index_to_price(221819, 6, 6).is_err());
+        assert!(
+            JupiterInvariant::price_to_tick_index(rust_decimal::Decimal::ONE, 6, 6, 0).is_err()
+        );
+    }
+
+    #[test]
+    fn test_ui_amounts() {
+        assert_eq!(
+            JupiterInvariant::amount_to_ui(1_500_000, 6).unwrap(),
+            rust_decimal::Decimal::new(15, 1)
+        );
+        assert_eq!(
+            JupiterInvariant::amount_to_ui(u64::MAX, 9).unwrap(),
+            rust_decimal::Decimal::from_i128_with_scale(u64::MAX as i128, 9)
+        );
+        assert!(JupiterInvariant::amount_to_ui(1, 29).is_err());
+
+        assert_eq!(
+            JupiterInvariant::ui_to_amount(rust_decimal::Decimal::new(15, 1), 6).unwrap(),
+            1_500_000
+        );
+        // rounded down
+        assert_eq!(
+            JupiterInvariant::ui_to_amount(rust_decimal::Decimal::new(123456789, 9), 6).unwrap(),
+            123456
+        );
+        assert!(JupiterInvariant::ui_to_amount(rust_decimal::Decimal::new(-1, 0), 6).is_err());
+        assert!(JupiterInvariant::ui_to_amount(rust_decimal::Decimal::from(u64::MAX), 6).is_err());
+
+        let result = crate::swap::InvariantSwapResult {
+            in_amount: 2_000_000_000,
+            out_amount: 30_250_000,
+            fee_amount: 1_000_000,
+            ..Default::default()
+        };
+        let ui_amounts = result.ui_amounts(9, 6).unwrap();
+        assert_eq!(
+            ui_amounts.to_string(),
+            "in: 2.000000000, out: 30.250000, fee: 0.001000000"
+        );
+    }
+
+    #[test]
+    fn test_price_impact_to_sqrt_price_limit() {
+        let mut jupiter_invariant = JupiterInvariant::default();
+        jupiter_invariant.pool.tick_spacing = 1;
+        jupiter_invariant.pool.sqrt_price = Price::from_integer(1);
+        let max_price_impact = rust_decimal::Decimal::new(19, 2);
+
+        // 1 -> 0.81
+        {
+            let result = jupiter_invariant
+                .price_impact_to_sqrt_price_limit(max_price_impact, true)
+                .unwrap();
+            assert_eq!(result, Price::new(900000000000000000000000));
+        }
+        // 1 -> 1.234567901234...
+        {
+            let result = jupiter_invariant
+                .price_impact_to_sqrt_price_limit(max_price_impact, false)
+                .unwrap();
+            assert_eq!(result, Price::new(1111111111111111111111111));
+        }
+        // out of range
+        {
+            let result = jupiter_invariant
+                .price_impact_to_sqrt_price_limit(rust_decimal::Decimal::ONE, true);
+            assert!(result.is_err());
+        }
+    }
 
     #[test]
     fn test_calculate_price_impact() {
@@ -299,7 +827,391 @@ mod tests {
             let a = Price::max_instance();
             let b = Price::new(100000);
             let result = JupiterInvariant::calculate_price_impact(a, b);
-            assert_eq!(result, Err("Price out of range"));
+            assert_eq!(result, Err(InvariantError::PriceOutOfRange));
         }
     }
+
+    #[test]
+    fn test_misaligned_current_tick_index() {
+        let mut jupiter_invariant = JupiterInvariant::default();
+        jupiter_invariant.pool.tick_spacing = 10;
+        jupiter_invariant.pool.current_tick_index = 3;
+        let expected_error = InvariantError::TickSpacingViolation {
+            tick_index: 3,
+            tick_spacing: 10,
+        };
+
+        assert_eq!(
+            jupiter_invariant.get_ticks_addresses_around(),
+            Err(expected_error.clone())
+        );
+        assert_eq!(
+            jupiter_invariant.ticks_accounts_outdated(),
+            Err(expected_error)
+        );
+        // pool and tickmap are still requested, so the state can recover on the next update
+        assert_eq!(jupiter_invariant.get_accounts_to_update().len(), 2);
+
+        let quote_params = QuoteParams {
+            in_amount: 1000,
+            input_mint: jupiter_invariant.pool.token_x,
+            output_mint: jupiter_invariant.pool.token_y,
+        };
+        assert!(jupiter_invariant.quote(&quote_params).is_err());
+    }
+
+    #[test]
+    fn test_zero_tick_spacing() {
+        let jupiter_invariant = JupiterInvariant::default();
+
+        assert_eq!(
+            jupiter_invariant.get_ticks_addresses_around(),
+            Err(InvariantError::TickSpacingViolation {
+                tick_index: 0,
+                tick_spacing: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_find_closest_tick_indexes_at_tickmap_edges() {
+        let mut jupiter_invariant = JupiterInvariant::default();
+        jupiter_invariant.pool.tick_spacing = 1;
+
+        // upper edge of the tickmap
+        {
+            jupiter_invariant.pool.current_tick_index = TICK_LIMIT - 1;
+            let result = jupiter_invariant
+                .find_closest_tick_indexes(TICK_CROSSES_PER_IX, PriceDirection::UP)
+                .unwrap();
+            assert_eq!(result, Vec::<i32>::new());
+        }
+        // lower edge of the tickmap
+        {
+            jupiter_invariant.pool.current_tick_index = -TICK_LIMIT;
+            jupiter_invariant.tickmap.bitmap[0] = 1;
+            let result = jupiter_invariant
+                .find_closest_tick_indexes(TICK_CROSSES_PER_IX, PriceDirection::DOWN)
+                .unwrap();
+            assert_eq!(result, vec![-TICK_LIMIT]);
+        }
+        // current tick index outside of the tickmap
+        {
+            jupiter_invariant.pool.current_tick_index = 2 * TICK_LIMIT;
+            let above = jupiter_invariant
+                .find_closest_tick_indexes(TICK_CROSSES_PER_IX, PriceDirection::UP)
+                .unwrap();
+            let below = jupiter_invariant
+                .find_closest_tick_indexes(TICK_CROSSES_PER_IX, PriceDirection::DOWN)
+                .unwrap();
+            assert_eq!(above, Vec::<i32>::new());
+            assert_eq!(below, vec![-TICK_LIMIT]);
+        }
+    }
+
+    #[test]
+    fn test_update_with_malformed_accounts() {
+        let (market_key, tickmap_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut jupiter_invariant = JupiterInvariant {
+            market_key,
+            ..Default::default()
+        };
+        jupiter_invariant.pool.tickmap = tickmap_key;
+
+        // missing accounts leave the state untouched
+        {
+            jupiter_invariant.update(&HashMap::new()).unwrap();
+        }
+        // data shorter than the discriminator
+        {
+            let accounts_map = HashMap::from([(market_key, vec![0u8; 3]), (tickmap_key, vec![])]);
+            let err = jupiter_invariant.update(&accounts_map).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<InvariantError>(),
+                Some(InvariantError::DeserializationFailed { key, .. }) if *key == market_key
+            ));
+        }
+        // truncated pool and tickmap
+        {
+            let accounts_map = HashMap::from([
+                (market_key, [Pool::discriminator(), [0u8; 8]].concat()),
+                (tickmap_key, [Tickmap::discriminator(), [0u8; 8]].concat()),
+            ]);
+            let err = jupiter_invariant.update(&accounts_map).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<InvariantError>(),
+                Some(InvariantError::DeserializationFailed { key, .. }) if *key == market_key
+            ));
+        }
+        // unknown discriminator
+        {
+            let accounts_map =
+                HashMap::from([(market_key, vec![0u8; 16]), (tickmap_key, vec![0u8; 16])]);
+            let err = jupiter_invariant.update(&accounts_map).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<InvariantError>(),
+                Some(&InvariantError::InvalidDiscriminator {
+                    key: market_key,
+                    expected: "Pool"
+                })
+            );
+        }
+        // state is left untouched
+        assert_eq!(jupiter_invariant.pool.tickmap, tickmap_key);
+        assert!(jupiter_invariant.ticks.is_empty());
+    }
+
+    #[test]
+    fn test_discriminators() {
+        fn anchor_discriminator(name: &str) -> Vec<u8> {
+            hash(format!("account:{}", name).as_bytes()).to_bytes()[..ANCHOR_DISCRIMINATOR_SIZE]
+                .to_vec()
+        }
+
+        assert_eq!(
+            Pool::discriminator().to_vec(),
+            anchor_discriminator(Pool::NAME)
+        );
+        assert_eq!(
+            Tickmap::discriminator().to_vec(),
+            anchor_discriminator(Tickmap::NAME)
+        );
+        assert_eq!(
+            Tick::discriminator().to_vec(),
+            anchor_discriminator(Tick::NAME)
+        );
+    }
+
+    #[test]
+    fn test_update_with_mismatched_accounts() {
+        let builder = PoolBuilder::new(10, FixedPoint::new(0)).position(-10, 10, 1000);
+        let mut jupiter_invariant = builder.build().unwrap();
+        let mut accounts_map = builder.accounts_map().unwrap();
+
+        // tickmap passed as a tick
+        {
+            let tick_key = jupiter_invariant.tick_index_to_address(10);
+            let tickmap_data = accounts_map[&builder.tickmap_key].clone();
+            let _ = accounts_map.insert(tick_key, tickmap_data);
+            let err = jupiter_invariant.update(&accounts_map).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<InvariantError>(),
+                Some(&InvariantError::InvalidDiscriminator {
+                    key: tick_key,
+                    expected: "Tick"
+                })
+            );
+        }
+        // account owned by another program
+        {
+            let owner = Pubkey::new_unique();
+            let mut keyed_account = builder.keyed_account().unwrap();
+            keyed_account.account.owner = owner;
+            assert_eq!(
+                JupiterInvariant::new_from_keyed_account_checked(&keyed_account).err(),
+                Some(InvariantError::InvalidOwner {
+                    key: builder.market_key,
+                    owner
+                })
+            );
+
+            // the owner is checked only on request
+            assert!(JupiterInvariant::new_from_keyed_account(&keyed_account).is_ok());
+
+            let accounts = HashMap::from([(builder.market_key, keyed_account.account)]);
+            assert_eq!(
+                jupiter_invariant.update_with_accounts(&accounts),
+                Err(InvariantError::InvalidOwner {
+                    key: builder.market_key,
+                    owner
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_partial_update() {
+        let builder = PoolBuilder::new(10, FixedPoint::new(0))
+            .position(-100, 100, 1000)
+            .position(-20, 20, 500);
+        let mut jupiter_invariant = builder.build().unwrap();
+        let tick_key = jupiter_invariant.tick_index_to_address(-20);
+
+        // only the pool
+        {
+            let moved = builder.clone().current_tick_index(30);
+            let accounts_map = moved.accounts_map().unwrap();
+            let pool_map = HashMap::from([(
+                builder.market_key,
+                accounts_map[&builder.market_key].clone(),
+            )]);
+            jupiter_invariant.update(&pool_map).unwrap();
+            assert_eq!({ jupiter_invariant.pool.current_tick_index }, 30);
+            assert_eq!(jupiter_invariant.ticks.len(), 4);
+        }
+        // only a tick
+        {
+            let changed = builder.clone().position(-20, 20, 500);
+            let accounts_map = changed.accounts_map().unwrap();
+            let tick_map = HashMap::from([(tick_key, accounts_map[&tick_key].clone())]);
+            jupiter_invariant.update(&tick_map).unwrap();
+            assert_eq!(
+                { jupiter_invariant.ticks[&tick_key].liquidity_change },
+                Liquidity::new(1000)
+            );
+            assert_eq!({ jupiter_invariant.pool.current_tick_index }, 30);
+        }
+        // only the tickmap, ticks outside of the window are evicted
+        {
+            let closed = PoolBuilder {
+                ticks: Default::default(),
+                ..builder.clone()
+            }
+            .position(-100, 100, 1000);
+            let accounts_map = closed.accounts_map().unwrap();
+            let tickmap_map = HashMap::from([(
+                builder.tickmap_key,
+                accounts_map[&builder.tickmap_key].clone(),
+            )]);
+            jupiter_invariant.update(&tickmap_map).unwrap();
+            assert_eq!(jupiter_invariant.ticks.len(), 2);
+            assert!(!jupiter_invariant.ticks.contains_key(&tick_key));
+            assert!(!jupiter_invariant.ticks_accounts_outdated().unwrap());
+        }
+        // closed tick account
+        {
+            let closed_key = jupiter_invariant.tick_index_to_address(100);
+            jupiter_invariant
+                .update(&HashMap::from([(closed_key, vec![])]))
+                .unwrap();
+            assert!(!jupiter_invariant.ticks.contains_key(&closed_key));
+        }
+    }
+
+    #[test]
+    fn test_tick_update_before_tickmap() {
+        let builder = PoolBuilder::new(10, FixedPoint::new(0)).position(-100, 100, 1000);
+        let mut jupiter_invariant = builder.build().unwrap();
+        let opened = builder.clone().position(-50, 50, 1000);
+        let accounts_map = opened.accounts_map().unwrap();
+        let (lower_key, upper_key) = (
+            jupiter_invariant.tick_index_to_address(-50),
+            jupiter_invariant.tick_index_to_address(50),
+        );
+
+        // the tickmap does not mark the new ticks yet
+        {
+            let tick_map = HashMap::from([(lower_key, accounts_map[&lower_key].clone())]);
+            jupiter_invariant.update(&tick_map).unwrap();
+            assert!(jupiter_invariant.ticks.contains_key(&lower_key));
+            assert!(!jupiter_invariant.ticks_accounts_outdated().unwrap());
+        }
+        // the tickmap arrives, the other tick is still missing
+        {
+            let tickmap_map = HashMap::from([(
+                builder.tickmap_key,
+                accounts_map[&builder.tickmap_key].clone(),
+            )]);
+            jupiter_invariant.update(&tickmap_map).unwrap();
+            assert!(jupiter_invariant.ticks.contains_key(&lower_key));
+            assert!(jupiter_invariant.ticks_accounts_outdated().unwrap());
+        }
+        {
+            let tick_map = HashMap::from([(upper_key, accounts_map[&upper_key].clone())]);
+            jupiter_invariant.update(&tick_map).unwrap();
+            assert_eq!(jupiter_invariant.ticks.len(), 4);
+            assert!(!jupiter_invariant.ticks_accounts_outdated().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_ticks_window() {
+        let builder = PoolBuilder::new(10, FixedPoint::new(0))
+            .position(-300, 300, 1000)
+            .position(-200, 200, 1000)
+            .position(-100, 100, 1000);
+        let mut jupiter_invariant = builder.build().unwrap();
+        assert_eq!(
+            jupiter_invariant
+                .get_ticks_addresses_around()
+                .unwrap()
+                .len(),
+            6
+        );
+
+        jupiter_invariant.ticks_window = TicksWindow {
+            up: 1,
+            down: 2,
+            opposite_tick: false,
+        };
+        assert_eq!(
+            jupiter_invariant.get_ticks_addresses_around().unwrap(),
+            jupiter_invariant.tick_indexes_to_addresses(&[-200, -100, 100])
+        );
+
+        // a single tick in both directions is kept for the opposite tick
+        jupiter_invariant.ticks_window = TicksWindow {
+            up: 0,
+            down: 0,
+            opposite_tick: true,
+        };
+        assert_eq!(
+            jupiter_invariant.get_ticks_addresses_around().unwrap(),
+            jupiter_invariant.tick_indexes_to_addresses(&[-100, 100])
+        );
+        assert_eq!(
+            jupiter_invariant.opposite_tick_address(true).unwrap(),
+            Some(jupiter_invariant.tick_index_to_address(100))
+        );
+    }
+
+    #[test]
+    fn test_tick_address_cache() {
+        let jupiter_invariant = JupiterInvariant {
+            program_id: invariant_types::ID,
+            market_key: Pubkey::new_unique(),
+            ..Default::default()
+        };
+        let clone = jupiter_invariant.clone();
+        let expected = Pubkey::find_program_address(
+            &[
+                invariant_types::TICK_SEED.as_bytes(),
+                jupiter_invariant.market_key.as_ref(),
+                &(-20i32).to_le_bytes(),
+            ],
+            &invariant_types::ID,
+        )
+        .0;
+
+        assert!(jupiter_invariant.tick_addresses.is_empty());
+        assert_eq!(jupiter_invariant.tick_index_to_address(-20), expected);
+        assert_eq!(jupiter_invariant.tick_addresses.len(), 1);
+        // clones share the cache
+        assert_eq!(clone.tick_addresses.len(), 1);
+        assert_eq!(clone.tick_index_to_address(-20), expected);
+        assert_eq!(clone.tick_addresses.len(), 1);
+        // a clone bound to another market derives its own addresses
+        let other_market = JupiterInvariant {
+            market_key: Pubkey::new_unique(),
+            ..clone
+        };
+        assert_ne!(other_market.tick_index_to_address(-20), expected);
+        assert_eq!(other_market.tick_addresses.len(), 2);
+    }
+
+    #[test]
+    fn test_tick_address_cache_eviction() {
+        let builder = PoolBuilder::new(10, FixedPoint::new(0)).position(-100, 100, 1000);
+        let mut jupiter_invariant = builder.build().unwrap();
+        let far_indexes: Vec<i32> = (1..=50).map(|i| i * 1000).collect();
+        let _ = jupiter_invariant.tick_indexes_to_addresses(&far_indexes);
+        assert!(jupiter_invariant.tick_addresses.len() >= far_indexes.len());
+
+        // addresses outside of the ticks window are evicted together with the ticks
+        jupiter_invariant
+            .update(&builder.accounts_map().unwrap())
+            .unwrap();
+        let window = jupiter_invariant.get_ticks_addresses_around().unwrap();
+        assert_eq!(jupiter_invariant.tick_addresses.len(), window.len());
+    }
 }